@@ -0,0 +1,152 @@
+use clap::ValueEnum;
+use serde_json::{json, Map, Value};
+use std::collections::VecDeque;
+
+use crate::types::*;
+
+/// Where sub-agent conversations go in `--format chat-json`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum ChatSubAgents {
+    /// As a `sub_agent_messages` array on the `tool` message answering the `task` call
+    #[default]
+    Nest,
+    /// Inline in the parent's array, each message named after the sub-agent session
+    Flatten,
+}
+
+/// A session as an OpenAI-style chat `messages` array, for `--format chat-json`.
+///
+/// ```json
+/// [
+///   { "role": "user", "content": "…" },
+///   { "role": "assistant", "content": "…" | null,
+///     "tool_calls": [{ "id": "…", "type": "function",
+///                      "function": { "name": "bash", "arguments": "{…}" } }] },
+///   { "role": "tool", "tool_call_id": "…", "content": "…" }
+/// ]
+/// ```
+///
+/// A message's text parts are joined with blank lines into `content`; its tool parts
+/// become `tool_calls` (`arguments` is the input as a JSON string), each answered by a
+/// `tool` message holding the output or error. Reasoning, steps, patches and permission
+/// parts have no place in the schema and are left out, as are messages left with
+/// neither text nor tool calls.
+///
+/// With `ChatSubAgents::Nest`, each sub-agent goes on the oldest `task` result before it
+/// that has none yet; a sub-agent with no such call is flattened instead.
+pub fn chat_messages(resolved: &ResolvedSession, sub_agents: ChatSubAgents) -> Vec<Value> {
+    let mut out = Vec::new();
+    push_items(&mut out, &resolved.messages, None, sub_agents);
+    out
+}
+
+fn push_items(
+    out: &mut Vec<Value>,
+    items: &[ResolvedConversationItem],
+    name: Option<&str>,
+    sub_agents: ChatSubAgents,
+) {
+    // Indices in `out` of `task` results not yet given their sub-agent, oldest first
+    let mut pending_tasks = VecDeque::new();
+    for item in items {
+        match item {
+            ResolvedConversationItem::Message(rm) => {
+                pending_tasks.extend(push_message(out, rm, name));
+            }
+            ResolvedConversationItem::SubAgent { session, messages } => {
+                let slot = match sub_agents {
+                    ChatSubAgents::Nest => pending_tasks.pop_front(),
+                    ChatSubAgents::Flatten => None,
+                };
+                match slot {
+                    Some(i) => {
+                        let mut nested = Vec::new();
+                        push_items(&mut nested, messages, None, sub_agents);
+                        out[i]["sub_agent_messages"] = json!(nested);
+                    }
+                    None => push_items(out, messages, Some(&session.id), sub_agents),
+                }
+            }
+        }
+    }
+}
+
+/// Push the message and its tool results; returns the indices of `task` results.
+fn push_message(out: &mut Vec<Value>, rm: &ResolvedMessage, name: Option<&str>) -> Vec<usize> {
+    let text = rm
+        .parts
+        .iter()
+        .filter_map(|p| match &p.kind {
+            PartKind::Text { text, .. } if !text.trim().is_empty() => Some(text.trim()),
+            _ => None,
+        })
+        .collect::<Vec<_>>()
+        .join("\n\n");
+    let tools: Vec<(String, &str, &ToolState)> = rm
+        .parts
+        .iter()
+        .filter_map(|p| match &p.kind {
+            PartKind::Tool {
+                call_id,
+                tool,
+                state,
+            } => Some((
+                call_id.clone().unwrap_or_else(|| p.id.clone()),
+                tool.as_str(),
+                state,
+            )),
+            _ => None,
+        })
+        .collect();
+    if text.is_empty() && tools.is_empty() {
+        return Vec::new();
+    }
+
+    let mut msg = Map::new();
+    msg.insert("role".into(), json!(rm.message.role));
+    if let Some(name) = name {
+        msg.insert("name".into(), json!(name));
+    }
+    let content = if text.is_empty() {
+        Value::Null
+    } else {
+        json!(text)
+    };
+    msg.insert("content".into(), content);
+    if !tools.is_empty() {
+        let calls: Vec<Value> = tools
+            .iter()
+            .map(|(id, tool, state)| {
+                let arguments = state
+                    .input
+                    .as_ref()
+                    .map_or_else(|| "{}".to_string(), |input| input.to_string());
+                json!({
+                    "id": id,
+                    "type": "function",
+                    "function": { "name": tool, "arguments": arguments },
+                })
+            })
+            .collect();
+        msg.insert("tool_calls".into(), json!(calls));
+    }
+    out.push(Value::Object(msg));
+
+    let mut tasks = Vec::new();
+    for (id, tool, state) in tools {
+        let content = state
+            .error
+            .as_deref()
+            .or(state.output.as_deref())
+            .unwrap_or("");
+        let mut result = json!({ "role": "tool", "tool_call_id": id, "content": content });
+        if let Some(name) = name {
+            result["name"] = json!(name);
+        }
+        if tool == "task" {
+            tasks.push(out.len());
+        }
+        out.push(result);
+    }
+    tasks
+}
@@ -0,0 +1,80 @@
+use anyhow::{Context, Result};
+use serde_json::{json, Value};
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use crate::types::*;
+
+/// NDJSON event stream for `--emit-events`: one JSON object per line.
+pub struct EventSink {
+    out: Box<dyn Write>,
+}
+
+impl EventSink {
+    pub fn stdout() -> Self {
+        EventSink {
+            out: Box::new(BufWriter::new(io::stdout())),
+        }
+    }
+
+    pub fn file(path: &Path) -> Result<Self> {
+        let file = File::create(path).with_context(|| format!("creating {}", path.display()))?;
+        Ok(EventSink {
+            out: Box::new(BufWriter::new(file)),
+        })
+    }
+
+    /// Write `{"event": <event>, ...fields}` as one line.
+    pub fn emit(&mut self, event: &str, fields: Value) -> Result<()> {
+        let mut obj = json!({ "event": event });
+        if let (Some(map), Value::Object(extra)) = (obj.as_object_mut(), fields) {
+            map.extend(extra);
+        }
+        writeln!(self.out, "{}", obj).context("writing event")
+    }
+
+    /// Emit `session_start` followed by one `message` event per message, sub-agents included.
+    pub fn session(&mut self, project: &Project, rs: &ResolvedSession) -> Result<()> {
+        self.emit(
+            "session_start",
+            json!({
+                "id": rs.session.id,
+                "project": project.id,
+                "title": rs.session.title,
+                "created": rs.session.time.created.map(to_millis),
+            }),
+        )?;
+        self.messages(&rs.session.id, &rs.messages)
+    }
+
+    fn messages(&mut self, session_id: &str, items: &[ResolvedConversationItem]) -> Result<()> {
+        for item in items {
+            match item {
+                ResolvedConversationItem::Message(rm) => self.emit(
+                    "message",
+                    json!({
+                        "id": rm.message.id,
+                        "session": session_id,
+                        "role": rm.message.role,
+                        "model": rm.message.effective_model(),
+                        "created": rm.message.time.created.map(to_millis),
+                        "parts": rm.parts.len(),
+                    }),
+                )?,
+                ResolvedConversationItem::SubAgent { session, messages } => {
+                    self.emit(
+                        "subagent_start",
+                        json!({ "id": session.id, "parent": session_id, "title": session.title }),
+                    )?;
+                    self.messages(&session.id, messages)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> Result<()> {
+        self.out.flush().context("flushing events")
+    }
+}
@@ -0,0 +1,129 @@
+use crate::types::*;
+
+/// A code block the assistant produced, for `--extract-code`.
+#[derive(Debug)]
+pub struct Snippet {
+    /// File extension without the dot, e.g. `rs`
+    pub ext: String,
+    pub code: String,
+}
+
+/// Collect fenced code blocks from assistant text and the content of `write` / `edit`
+/// tool calls, sub-agents included, in conversation order.
+pub fn code_snippets(items: &[ResolvedConversationItem]) -> Vec<Snippet> {
+    let mut out = Vec::new();
+    collect(items, &mut out);
+    out
+}
+
+fn collect(items: &[ResolvedConversationItem], out: &mut Vec<Snippet>) {
+    for item in items {
+        match item {
+            ResolvedConversationItem::Message(rm) if rm.message.role == "assistant" => {
+                for part in &rm.parts {
+                    match &part.kind {
+                        PartKind::Text { text, .. } => {
+                            for (lang, code) in fenced_blocks(text) {
+                                push(out, ext_for_lang(&lang), code);
+                            }
+                        }
+                        PartKind::Tool { tool, state, .. } => {
+                            let Some(ref input) = state.input else {
+                                continue;
+                            };
+                            let key = match tool.as_str() {
+                                "write" => "content",
+                                "edit" => "newString",
+                                _ => continue,
+                            };
+                            if let Some(code) = input.get(key).and_then(|v| v.as_str()) {
+                                // Same inference as the fence language in `render_tool_input`
+                                let ext = input
+                                    .get("filePath")
+                                    .and_then(|v| v.as_str())
+                                    .and_then(|p| p.rsplit_once('.'))
+                                    .map(|(_, ext)| ext)
+                                    .unwrap_or("txt");
+                                push(out, ext.to_string(), code.to_string());
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            ResolvedConversationItem::Message(_) => {}
+            ResolvedConversationItem::SubAgent { messages, .. } => collect(messages, out),
+        }
+    }
+}
+
+fn push(out: &mut Vec<Snippet>, ext: String, code: String) {
+    if code.trim().is_empty() {
+        return;
+    }
+    let ext = if !ext.is_empty() && ext.chars().all(|c| c.is_ascii_alphanumeric()) {
+        ext.to_ascii_lowercase()
+    } else {
+        "txt".to_string()
+    };
+    out.push(Snippet { ext, code });
+}
+
+/// `(info-string language, body)` for every ``` or ~~~ fenced block in Markdown text.
+/// An unclosed block runs to the end of the text.
+fn fenced_blocks(text: &str) -> Vec<(String, String)> {
+    let mut blocks = Vec::new();
+    let mut open: Option<(char, usize, String, String)> = None;
+    for line in text.lines() {
+        let trimmed = line.trim_start();
+        match open {
+            None => {
+                let Some(ch) = trimmed.chars().next().filter(|c| *c == '`' || *c == '~') else {
+                    continue;
+                };
+                let len = trimmed.chars().take_while(|c| *c == ch).count();
+                if len >= 3 {
+                    let lang = trimmed[len..].split_whitespace().next().unwrap_or("");
+                    open = Some((ch, len, lang.to_string(), String::new()));
+                }
+            }
+            Some((ch, len, ref lang, ref mut body)) => {
+                let run = trimmed.chars().take_while(|c| *c == ch).count();
+                if run >= len && trimmed[run..].trim().is_empty() {
+                    blocks.push((lang.clone(), std::mem::take(body)));
+                    open = None;
+                } else {
+                    body.push_str(line);
+                    body.push('\n');
+                }
+            }
+        }
+    }
+    if let Some((_, _, lang, body)) = open {
+        blocks.push((lang, body));
+    }
+    blocks
+}
+
+/// File extension for a fence language tag; unknown tags are used as-is.
+fn ext_for_lang(lang: &str) -> String {
+    let lang = lang.to_ascii_lowercase();
+    let ext = match lang.as_str() {
+        "" | "text" | "plaintext" => "txt",
+        "rust" => "rs",
+        "python" | "python3" => "py",
+        "javascript" | "node" => "js",
+        "typescript" => "ts",
+        "bash" | "shell" | "sh" | "zsh" | "console" => "sh",
+        "yaml" => "yml",
+        "ruby" => "rb",
+        "golang" => "go",
+        "c++" | "cxx" => "cpp",
+        "csharp" | "c#" => "cs",
+        "kotlin" => "kt",
+        "markdown" => "md",
+        "patch" => "diff",
+        other => other,
+    };
+    ext.to_string()
+}
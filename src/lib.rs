@@ -0,0 +1,102 @@
+//! Library side of oc-export: load OpenCode storage, resolve sessions, render Markdown.
+//!
+//! [`OcExport`] is the entry point for tools that want to browse sessions without
+//! exporting anything; the `loader`, `resolver` and `renderer` modules stay public for
+//! callers that need the raw pipeline.
+
+pub mod chat;
+pub mod events;
+pub mod extract;
+pub mod loader;
+pub mod renderer;
+pub mod resolver;
+pub mod sqlite;
+pub mod tree;
+pub mod types;
+pub mod validate;
+
+use anyhow::Result;
+use std::path::Path;
+
+use crate::loader::StorageData;
+use crate::types::*;
+
+/// Loaded OpenCode storage with query accessors. Sessions are resolved on demand.
+pub struct OcExport {
+    data: StorageData,
+}
+
+impl OcExport {
+    /// Load storage from a directory or `.zip` backup, skipping unreadable files.
+    pub fn open(storage_path: &Path) -> Result<Self> {
+        Ok(Self::from_data(loader::load_all(storage_path, false)?))
+    }
+
+    /// Load storage from the platform default location.
+    pub fn open_default() -> Result<Self> {
+        Self::open(&loader::default_storage_path())
+    }
+
+    pub fn from_data(data: StorageData) -> Self {
+        OcExport { data }
+    }
+
+    pub fn projects(&self) -> &[Project] {
+        &self.data.projects
+    }
+
+    /// Look up a project by ID.
+    pub fn project(&self, id: &str) -> Option<&Project> {
+        self.data.projects.iter().find(|p| p.id == id)
+    }
+
+    /// Top-level sessions of a project (sub-agents excluded), oldest first.
+    pub fn sessions_for(&self, project: &Project) -> Vec<&Session> {
+        let mut sessions: Vec<&Session> = self
+            .data
+            .sessions_by_project
+            .get(&project.id)
+            .into_iter()
+            .flatten()
+            .filter_map(|id| self.data.sessions.get(id))
+            .filter(|s| s.parent_id.is_none())
+            .collect();
+        sessions.sort_by_key(|s| s.time.created.unwrap_or(0));
+        sessions
+    }
+
+    /// Sub-agent sessions spawned directly by `session`, oldest first.
+    pub fn children(&self, session: &Session) -> Vec<&Session> {
+        let mut children: Vec<&Session> = self
+            .data
+            .sessions
+            .values()
+            .filter(|s| s.parent_id.as_deref() == Some(session.id.as_str()))
+            .collect();
+        children.sort_by_key(|s| s.time.created.unwrap_or(0));
+        children
+    }
+
+    pub fn session(&self, id: &str) -> Option<&Session> {
+        self.data.sessions.get(id)
+    }
+
+    /// Messages of a session without their parts, sorted by time.
+    pub fn messages(&self, session_id: &str) -> &[Message] {
+        self.data
+            .messages_by_session
+            .get(session_id)
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+
+    /// Build the full conversation tree for one session, ready for rendering.
+    pub fn resolve(&self, session_id: &str) -> Option<ResolvedSession> {
+        resolver::resolve_one(&self.data, session_id)
+    }
+
+    /// The underlying maps, for callers that need the raw pipeline.
+    pub fn data(&self) -> &StorageData {
+        &self.data
+    }
+}
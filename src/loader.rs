@@ -1,7 +1,10 @@
 use anyhow::{Context, Result};
-use std::collections::HashMap;
-use std::fs;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::Read;
 use std::path::{Path, PathBuf};
+use zip::ZipArchive;
 
 use crate::types::*;
 
@@ -22,34 +25,155 @@ pub struct StorageData {
     pub sessions_by_project: HashMap<String, Vec<String>>,
 }
 
-/// Detect the default opencode storage path for this platform.
-pub fn default_storage_path() -> PathBuf {
+/// Detect the current user's home directory for this platform.
+pub fn home_dir() -> String {
     if cfg!(target_os = "macos") || cfg!(target_os = "linux") {
-        let home = std::env::var("HOME").unwrap_or_else(|_| ".".to_string());
-        PathBuf::from(home)
-            .join(".local")
-            .join("share")
-            .join("opencode")
-            .join("storage")
+        std::env::var("HOME").unwrap_or_else(|_| ".".to_string())
     } else {
         // Windows
-        let profile = std::env::var("USERPROFILE").unwrap_or_else(|_| ".".to_string());
-        PathBuf::from(profile)
-            .join(".local")
-            .join("share")
-            .join("opencode")
-            .join("storage")
+        std::env::var("USERPROFILE").unwrap_or_else(|_| ".".to_string())
     }
 }
 
-/// Load all data from the storage directory.
-pub fn load_all(storage_dir: &Path) -> Result<StorageData> {
-    let projects = load_projects(&storage_dir.join("project"))?;
-    let (sessions, sessions_by_project) = load_sessions(&storage_dir.join("session"))?;
-    let messages_by_session = load_messages(&storage_dir.join("message"))?;
-    let parts_by_message = load_parts(&storage_dir.join("part"))?;
-    let diffs_by_session = load_session_diffs(&storage_dir.join("session_diff"))?;
-    let todos_by_session = load_todos(&storage_dir.join("todo"))?;
+/// Detect the default opencode storage path for this platform.
+pub fn default_storage_path() -> PathBuf {
+    PathBuf::from(home_dir())
+        .join(".local")
+        .join("share")
+        .join("opencode")
+        .join("storage")
+}
+
+/// Load all data from the storage directory (or a `.zip` archive of it).
+///
+/// With `strict`, the first file that fails to read or parse aborts the load
+/// instead of being skipped with a warning.
+pub fn load_all(storage_path: &Path, strict: bool) -> Result<StorageData> {
+    load(storage_path, strict, None, None, true)
+}
+
+/// `load_all`, calling `on_file(done, total)` as each JSON file is loaded.
+///
+/// Projects and sessions are loaded first; the rest of the storage tree is then listed
+/// once to find `total`, so this costs an extra directory walk over `load_all`.
+pub fn load_all_with_progress(
+    storage_path: &Path,
+    strict: bool,
+    mut on_file: impl FnMut(usize, usize),
+) -> Result<StorageData> {
+    load(storage_path, strict, None, Some(&mut on_file), true)
+}
+
+/// `load_all` restricted to the projects `keep_project` accepts.
+///
+/// Every project and session file is still read (they are small), but only the kept
+/// projects' sessions are returned, and only their messages, parts, diffs and todos
+/// are read from disk.
+pub fn load_matching(
+    storage_path: &Path,
+    strict: bool,
+    keep_project: impl Fn(&Project) -> bool,
+) -> Result<StorageData> {
+    load(storage_path, strict, Some(&keep_project), None, true)
+}
+
+/// `load_matching` without the content: projects, sessions and messages only, with
+/// no parts, diffs or todos. Enough for per-session models and token counts.
+pub fn load_headers(
+    storage_path: &Path,
+    strict: bool,
+    keep_project: impl Fn(&Project) -> bool,
+) -> Result<StorageData> {
+    load(storage_path, strict, Some(&keep_project), None, false)
+}
+
+/// `load_matching`, calling `on_file(done, total)` as each JSON file is loaded.
+pub fn load_matching_with_progress(
+    storage_path: &Path,
+    strict: bool,
+    keep_project: impl Fn(&Project) -> bool,
+    mut on_file: impl FnMut(usize, usize),
+) -> Result<StorageData> {
+    load(
+        storage_path,
+        strict,
+        Some(&keep_project),
+        Some(&mut on_file),
+        true,
+    )
+}
+
+fn load(
+    storage_path: &Path,
+    strict: bool,
+    keep_project: Option<&dyn Fn(&Project) -> bool>,
+    on_file: Option<&mut dyn FnMut(usize, usize)>,
+    with_content: bool,
+) -> Result<StorageData> {
+    let storage = Storage::open(storage_path)?;
+    let mut progress = Progress {
+        done: 0,
+        total: 0,
+        on_file: None,
+    };
+    let p = &mut progress;
+    let mut projects = load_projects(&storage, &storage.dir("project"), strict, p)?;
+    let project_ids: Option<HashSet<String>> = keep_project.map(|keep| {
+        projects.retain(|project| keep(project));
+        projects.iter().map(|project| project.id.clone()).collect()
+    });
+    let (sessions, sessions_by_project) = load_sessions(
+        &storage,
+        &storage.dir("session"),
+        strict,
+        p,
+        project_ids.as_ref(),
+    )?;
+    let session_ids: Option<HashSet<String>> = project_ids
+        .as_ref()
+        .map(|_| sessions.keys().cloned().collect());
+    let only_sessions = session_ids.as_ref();
+    // Projects and sessions are small; the count covers the messages and parts they select
+    if let Some(on_file) = on_file {
+        p.total = p.done + count_json_files(&storage, only_sessions)?;
+        p.on_file = Some(on_file);
+    }
+
+    let messages_by_session =
+        load_messages(&storage, &storage.dir("message"), strict, p, only_sessions)?;
+    if !with_content {
+        return Ok(StorageData {
+            projects,
+            sessions,
+            messages_by_session,
+            parts_by_message: HashMap::new(),
+            diffs_by_session: HashMap::new(),
+            todos_by_session: HashMap::new(),
+            sessions_by_project,
+        });
+    }
+    let message_ids: Option<HashSet<String>> = only_sessions.map(|_| {
+        messages_by_session
+            .values()
+            .flatten()
+            .map(|m| m.id.clone())
+            .collect()
+    });
+    let parts_by_message = load_parts(
+        &storage,
+        &storage.dir("part"),
+        strict,
+        p,
+        message_ids.as_ref(),
+    )?;
+    let diffs_by_session = load_session_diffs(
+        &storage,
+        &storage.dir("session_diff"),
+        strict,
+        p,
+        only_sessions,
+    )?;
+    let todos_by_session = load_todos(&storage, &storage.dir("todo"), strict, p, only_sessions)?;
 
     Ok(StorageData {
         projects,
@@ -62,21 +186,278 @@ pub fn load_all(storage_dir: &Path) -> Result<StorageData> {
     })
 }
 
+// ── Progress ────────────────────────────────────────────────────────
+
+/// File counter behind `load_all_with_progress`.
+struct Progress<'a> {
+    done: usize,
+    total: usize,
+    on_file: Option<&'a mut dyn FnMut(usize, usize)>,
+}
+
+impl Progress<'_> {
+    fn tick(&mut self) {
+        self.done += 1;
+        if let Some(ref mut on_file) = self.on_file {
+            on_file(self.done, self.total);
+        }
+    }
+}
+
+/// JSON files that `load` will read after the projects and sessions: messages, parts,
+/// diffs and todos.
+///
+/// With `session_ids`, only those sessions' message dirs are counted, then the part dirs
+/// named after the message files found (file stems stand in for message IDs here, so
+/// nothing is parsed), and the diffs and todos of those sessions.
+fn count_json_files(storage: &Storage, session_ids: Option<&HashSet<String>>) -> Result<usize> {
+    let (messages, message_ids) =
+        count_subdir_files(storage, &storage.dir("message"), session_ids)?;
+    let (parts, _) = count_subdir_files(storage, &storage.dir("part"), message_ids.as_ref())?;
+    let mut total = messages + parts;
+    for name in ["session_diff", "todo"] {
+        let dir = storage.dir(name);
+        if storage.exists(&dir) {
+            total += storage
+                .json_files(&dir)?
+                .iter()
+                .filter(|path| wanted(session_ids, &file_stem(path)))
+                .count();
+        }
+    }
+    Ok(total)
+}
+
+/// JSON files in the wanted subdirs of `dir`, and with `only`, the files' stems.
+fn count_subdir_files(
+    storage: &Storage,
+    dir: &Path,
+    only: Option<&HashSet<String>>,
+) -> Result<(usize, Option<HashSet<String>>)> {
+    let mut count = 0;
+    let mut stems = only.map(|_| HashSet::new());
+    if storage.exists(dir) {
+        for sub in storage.subdirs(dir)? {
+            if !wanted(only, &file_name(&sub)) {
+                continue;
+            }
+            for path in storage.json_files(&sub)? {
+                count += 1;
+                if let Some(ref mut stems) = stems {
+                    stems.insert(file_stem(&path));
+                }
+            }
+        }
+    }
+    Ok((count, stems))
+}
+
+// ── Storage access ──────────────────────────────────────────────────
+
+/// Read-only view of the storage tree: either a real directory or a zip archive.
+///
+/// Locations are `PathBuf`s in both cases: real paths for `Dir`, archive entry
+/// names for `Zip`. Loaders only pass them back into `Storage` and print them.
+enum Storage {
+    Dir(PathBuf),
+    Zip {
+        archive: RefCell<ZipArchive<File>>,
+        /// Entry-name prefix of the storage root inside the archive (`""` or `.../`)
+        root: String,
+        /// directory entry name -> child directory entry names
+        subdirs: HashMap<String, BTreeSet<String>>,
+        /// directory entry name -> file entry names directly inside it
+        files: HashMap<String, Vec<String>>,
+    },
+}
+
+impl Storage {
+    fn open(path: &Path) -> Result<Self> {
+        if path.is_file()
+            && path
+                .extension()
+                .is_some_and(|e| e.eq_ignore_ascii_case("zip"))
+        {
+            Self::open_zip(path)
+        } else {
+            Ok(Storage::Dir(path.to_path_buf()))
+        }
+    }
+
+    fn open_zip(path: &Path) -> Result<Self> {
+        let file = File::open(path).with_context(|| format!("opening {}", path.display()))?;
+        let archive =
+            ZipArchive::new(file).with_context(|| format!("reading zip {}", path.display()))?;
+
+        // Index entries by parent directory; zips don't always carry directory entries
+        let mut subdirs: HashMap<String, BTreeSet<String>> = HashMap::new();
+        let mut files: HashMap<String, Vec<String>> = HashMap::new();
+        for name in archive.file_names() {
+            if name.ends_with('/') {
+                continue;
+            }
+            let (parent, _) = name.rsplit_once('/').unwrap_or(("", name));
+            files
+                .entry(parent.to_string())
+                .or_default()
+                .push(name.to_string());
+            let mut dir = parent;
+            while let Some((grandparent, _)) = dir.rsplit_once('/') {
+                subdirs
+                    .entry(grandparent.to_string())
+                    .or_default()
+                    .insert(dir.to_string());
+                dir = grandparent;
+            }
+            if !dir.is_empty() {
+                subdirs
+                    .entry(String::new())
+                    .or_default()
+                    .insert(dir.to_string());
+            }
+        }
+
+        // The storage root is the shallowest directory holding both session/ and message/,
+        // so archives of `storage/` itself or of a parent folder both work.
+        let root = subdirs
+            .iter()
+            .filter(|(dir, children)| {
+                let prefix = if dir.is_empty() {
+                    String::new()
+                } else {
+                    format!("{}/", dir)
+                };
+                children.contains(&format!("{}session", prefix))
+                    && children.contains(&format!("{}message", prefix))
+            })
+            .map(|(dir, _)| dir.clone())
+            .min_by_key(|dir| dir.len())
+            .map(|dir| {
+                if dir.is_empty() {
+                    dir
+                } else {
+                    format!("{}/", dir)
+                }
+            })
+            .unwrap_or_default();
+
+        Ok(Storage::Zip {
+            archive: RefCell::new(archive),
+            root,
+            subdirs,
+            files,
+        })
+    }
+
+    /// Location of a top-level storage subdirectory such as `session`.
+    fn dir(&self, name: &str) -> PathBuf {
+        match self {
+            Storage::Dir(root) => root.join(name),
+            Storage::Zip { root, .. } => PathBuf::from(format!("{}{}", root, name)),
+        }
+    }
+
+    fn exists(&self, dir: &Path) -> bool {
+        match self {
+            Storage::Dir(_) => dir.exists(),
+            Storage::Zip { subdirs, files, .. } => {
+                let key = entry_name(dir);
+                subdirs.contains_key(&key) || files.contains_key(&key)
+            }
+        }
+    }
+
+    /// Immediate subdirectories of `dir`.
+    fn subdirs(&self, dir: &Path) -> Result<Vec<PathBuf>> {
+        match self {
+            Storage::Dir(_) => {
+                let mut out = Vec::new();
+                for entry in
+                    fs::read_dir(dir).with_context(|| format!("reading {}", dir.display()))?
+                {
+                    let path = entry?.path();
+                    if path.is_dir() {
+                        out.push(path);
+                    }
+                }
+                Ok(out)
+            }
+            Storage::Zip { subdirs, .. } => Ok(subdirs
+                .get(&entry_name(dir))
+                .map(|s| s.iter().map(PathBuf::from).collect())
+                .unwrap_or_default()),
+        }
+    }
+
+    /// `.json` files directly inside `dir`.
+    fn json_files(&self, dir: &Path) -> Result<Vec<PathBuf>> {
+        match self {
+            Storage::Dir(_) => {
+                let mut out = Vec::new();
+                for entry in
+                    fs::read_dir(dir).with_context(|| format!("reading {}", dir.display()))?
+                {
+                    let path = entry?.path();
+                    if path.extension().is_some_and(|e| e == "json") {
+                        out.push(path);
+                    }
+                }
+                Ok(out)
+            }
+            Storage::Zip { files, .. } => Ok(files
+                .get(&entry_name(dir))
+                .map(|f| {
+                    f.iter()
+                        .filter(|name| name.ends_with(".json"))
+                        .map(PathBuf::from)
+                        .collect()
+                })
+                .unwrap_or_default()),
+        }
+    }
+
+    fn read(&self, path: &Path) -> Result<Vec<u8>> {
+        match self {
+            Storage::Dir(_) => {
+                fs::read(path).with_context(|| format!("reading {}", path.display()))
+            }
+            Storage::Zip { archive, .. } => {
+                let mut archive = archive.borrow_mut();
+                let mut entry = archive
+                    .by_name(&entry_name(path))
+                    .with_context(|| format!("reading {}", path.display()))?;
+                let mut data = Vec::new();
+                entry
+                    .read_to_end(&mut data)
+                    .with_context(|| format!("reading {}", path.display()))?;
+                Ok(data)
+            }
+        }
+    }
+}
+
+/// Zip entry names always use `/`, whatever the host separator.
+fn entry_name(path: &Path) -> String {
+    path.to_string_lossy().replace('\\', "/")
+}
+
 // ── Projects ────────────────────────────────────────────────────────
 
-fn load_projects(dir: &Path) -> Result<Vec<Project>> {
+fn load_projects(
+    storage: &Storage,
+    dir: &Path,
+    strict: bool,
+    progress: &mut Progress,
+) -> Result<Vec<Project>> {
     let mut projects = Vec::new();
-    if !dir.exists() {
+    if !storage.exists(dir) {
         return Ok(projects);
     }
-    for entry in fs::read_dir(dir).context("reading project dir")? {
-        let entry = entry?;
-        let path = entry.path();
-        if path.extension().is_some_and(|e| e == "json") {
-            match load_json::<Project>(&path) {
-                Ok(p) => projects.push(p),
-                Err(e) => eprintln!("warn: skipping project {:?}: {}", path, e),
-            }
+    for path in storage.json_files(dir).context("reading project dir")? {
+        progress.tick();
+        match load_json::<Project>(storage, &path, strict) {
+            Ok(p) => projects.push(p),
+            Err(e) => skip_or_fail(strict, "project", &path, e)?,
         }
     }
     projects.sort_by_key(|p| p.time.created.unwrap_or(0));
@@ -85,35 +466,35 @@ fn load_projects(dir: &Path) -> Result<Vec<Project>> {
 
 // ── Sessions ────────────────────────────────────────────────────────
 
-fn load_sessions(dir: &Path) -> Result<(HashMap<String, Session>, HashMap<String, Vec<String>>)> {
+/// (session_id -> Session, project_id -> Vec<session_id>)
+type SessionMaps = (HashMap<String, Session>, HashMap<String, Vec<String>>);
+
+fn load_sessions(
+    storage: &Storage,
+    dir: &Path,
+    strict: bool,
+    progress: &mut Progress,
+    only_projects: Option<&HashSet<String>>,
+) -> Result<SessionMaps> {
     let mut sessions = HashMap::new();
     let mut by_project: HashMap<String, Vec<String>> = HashMap::new();
 
-    if !dir.exists() {
+    if !storage.exists(dir) {
         return Ok((sessions, by_project));
     }
-    for project_entry in fs::read_dir(dir).context("reading session dir")? {
-        let project_entry = project_entry?;
-        let project_dir = project_entry.path();
-        if !project_dir.is_dir() {
-            continue;
-        }
-        for entry in fs::read_dir(&project_dir)? {
-            let entry = entry?;
-            let path = entry.path();
-            if path.extension().is_some_and(|e| e == "json") {
-                match load_json::<Session>(&path) {
-                    Ok(s) => {
-                        by_project
-                            .entry(s.project_id.clone())
-                            .or_default()
-                            .push(s.id.clone());
-                        sessions.insert(s.id.clone(), s);
-                    }
-                    Err(e) => {
-                        eprintln!("warn: skipping session {:?}: {}", path, e)
-                    }
+    for project_dir in storage.subdirs(dir).context("reading session dir")? {
+        for path in storage.json_files(&project_dir)? {
+            progress.tick();
+            match load_json::<Session>(storage, &path, strict) {
+                Ok(s) if !wanted(only_projects, &s.project_id) => {}
+                Ok(s) => {
+                    by_project
+                        .entry(s.project_id.clone())
+                        .or_default()
+                        .push(s.id.clone());
+                    sessions.insert(s.id.clone(), s);
                 }
+                Err(e) => skip_or_fail(strict, "session", &path, e)?,
             }
         }
     }
@@ -122,35 +503,37 @@ fn load_sessions(dir: &Path) -> Result<(HashMap<String, Session>, HashMap<String
 
 // ── Messages ────────────────────────────────────────────────────────
 
-fn load_messages(dir: &Path) -> Result<HashMap<String, Vec<Message>>> {
+fn load_messages(
+    storage: &Storage,
+    dir: &Path,
+    strict: bool,
+    progress: &mut Progress,
+    only_sessions: Option<&HashSet<String>>,
+) -> Result<HashMap<String, Vec<Message>>> {
     let mut by_session: HashMap<String, Vec<Message>> = HashMap::new();
-    if !dir.exists() {
+    if !storage.exists(dir) {
         return Ok(by_session);
     }
-    for session_entry in fs::read_dir(dir).context("reading message dir")? {
-        let session_entry = session_entry?;
-        let session_dir = session_entry.path();
-        if !session_dir.is_dir() {
+    for session_dir in storage.subdirs(dir).context("reading message dir")? {
+        let session_id = file_name(&session_dir);
+        if !wanted(only_sessions, &session_id) {
             continue;
         }
-        let session_id = session_dir
-            .file_name()
-            .unwrap()
-            .to_string_lossy()
-            .to_string();
         let mut msgs = Vec::new();
-        for entry in fs::read_dir(&session_dir)? {
-            let entry = entry?;
-            let path = entry.path();
-            if path.extension().is_some_and(|e| e == "json") {
-                match load_json::<Message>(&path) {
-                    Ok(m) => msgs.push(m),
-                    Err(e) => {
-                        eprintln!("warn: skipping message {:?}: {}", path, e)
-                    }
-                }
+        for path in storage.json_files(&session_dir)? {
+            progress.tick();
+            match load_json::<Message>(storage, &path, strict) {
+                Ok(m) => msgs.push(m),
+                Err(e) => skip_or_fail(strict, "message", &path, e)?,
             }
         }
+        let mut msgs = dedupe_by_id(
+            msgs,
+            "message",
+            &session_dir,
+            |m| &m.id,
+            |m| m.time.completed,
+        );
         msgs.sort_by_key(|m| m.time.created.unwrap_or(0));
         by_session.insert(session_id, msgs);
     }
@@ -159,31 +542,31 @@ fn load_messages(dir: &Path) -> Result<HashMap<String, Vec<Message>>> {
 
 // ── Parts ───────────────────────────────────────────────────────────
 
-fn load_parts(dir: &Path) -> Result<HashMap<String, Vec<Part>>> {
+fn load_parts(
+    storage: &Storage,
+    dir: &Path,
+    strict: bool,
+    progress: &mut Progress,
+    only_messages: Option<&HashSet<String>>,
+) -> Result<HashMap<String, Vec<Part>>> {
     let mut by_message: HashMap<String, Vec<Part>> = HashMap::new();
-    if !dir.exists() {
+    if !storage.exists(dir) {
         return Ok(by_message);
     }
-    for msg_entry in fs::read_dir(dir).context("reading part dir")? {
-        let msg_entry = msg_entry?;
-        let msg_dir = msg_entry.path();
-        if !msg_dir.is_dir() {
+    for msg_dir in storage.subdirs(dir).context("reading part dir")? {
+        let message_id = file_name(&msg_dir);
+        if !wanted(only_messages, &message_id) {
             continue;
         }
-        let message_id = msg_dir.file_name().unwrap().to_string_lossy().to_string();
         let mut parts = Vec::new();
-        for entry in fs::read_dir(&msg_dir)? {
-            let entry = entry?;
-            let path = entry.path();
-            if path.extension().is_some_and(|e| e == "json") {
-                match load_json::<Part>(&path) {
-                    Ok(p) => parts.push(p),
-                    Err(e) => {
-                        eprintln!("warn: skipping part {:?}: {}", path, e)
-                    }
-                }
+        for path in storage.json_files(&msg_dir)? {
+            progress.tick();
+            match load_json::<Part>(storage, &path, strict) {
+                Ok(p) => parts.push(p),
+                Err(e) => skip_or_fail(strict, "part", &path, e)?,
             }
         }
+        let mut parts = dedupe_by_id(parts, "part", &msg_dir, |p| &p.id, part_end);
         // Sort parts by their ID (lexicographic = chronological for these IDs)
         parts.sort_by(|a, b| a.id.cmp(&b.id));
         by_message.insert(message_id, parts);
@@ -193,25 +576,32 @@ fn load_parts(dir: &Path) -> Result<HashMap<String, Vec<Part>>> {
 
 // ── Session Diffs ───────────────────────────────────────────────────
 
-fn load_session_diffs(dir: &Path) -> Result<HashMap<String, Vec<DiffEntry>>> {
+fn load_session_diffs(
+    storage: &Storage,
+    dir: &Path,
+    strict: bool,
+    progress: &mut Progress,
+    only_sessions: Option<&HashSet<String>>,
+) -> Result<HashMap<String, Vec<DiffEntry>>> {
     let mut by_session = HashMap::new();
-    if !dir.exists() {
+    if !storage.exists(dir) {
         return Ok(by_session);
     }
-    for entry in fs::read_dir(dir).context("reading session_diff dir")? {
-        let entry = entry?;
-        let path = entry.path();
-        if path.extension().is_some_and(|e| e == "json") {
-            let session_id = path.file_stem().unwrap().to_string_lossy().to_string();
-            match load_json::<Vec<DiffEntry>>(&path) {
-                Ok(diffs) if !diffs.is_empty() => {
-                    by_session.insert(session_id, diffs);
-                }
-                Ok(_) => {} // empty array, skip
-                Err(e) => {
-                    eprintln!("warn: skipping session_diff {:?}: {}", path, e)
-                }
+    for path in storage
+        .json_files(dir)
+        .context("reading session_diff dir")?
+    {
+        let session_id = file_stem(&path);
+        if !wanted(only_sessions, &session_id) {
+            continue;
+        }
+        progress.tick();
+        match load_json::<Vec<DiffEntry>>(storage, &path, strict) {
+            Ok(diffs) if !diffs.is_empty() => {
+                by_session.insert(session_id, diffs);
             }
+            Ok(_) => {} // empty array, skip
+            Err(e) => skip_or_fail(strict, "session_diff", &path, e)?,
         }
     }
     Ok(by_session)
@@ -219,25 +609,29 @@ fn load_session_diffs(dir: &Path) -> Result<HashMap<String, Vec<DiffEntry>>> {
 
 // ── Todos ───────────────────────────────────────────────────────────
 
-fn load_todos(dir: &Path) -> Result<HashMap<String, Vec<TodoEntry>>> {
+fn load_todos(
+    storage: &Storage,
+    dir: &Path,
+    strict: bool,
+    progress: &mut Progress,
+    only_sessions: Option<&HashSet<String>>,
+) -> Result<HashMap<String, Vec<TodoEntry>>> {
     let mut by_session = HashMap::new();
-    if !dir.exists() {
+    if !storage.exists(dir) {
         return Ok(by_session);
     }
-    for entry in fs::read_dir(dir).context("reading todo dir")? {
-        let entry = entry?;
-        let path = entry.path();
-        if path.extension().is_some_and(|e| e == "json") {
-            let session_id = path.file_stem().unwrap().to_string_lossy().to_string();
-            match load_json::<Vec<TodoEntry>>(&path) {
-                Ok(todos) if !todos.is_empty() => {
-                    by_session.insert(session_id, todos);
-                }
-                Ok(_) => {}
-                Err(e) => {
-                    eprintln!("warn: skipping todo {:?}: {}", path, e)
-                }
+    for path in storage.json_files(dir).context("reading todo dir")? {
+        let session_id = file_stem(&path);
+        if !wanted(only_sessions, &session_id) {
+            continue;
+        }
+        progress.tick();
+        match load_json::<Vec<TodoEntry>>(storage, &path, strict) {
+            Ok(todos) if !todos.is_empty() => {
+                by_session.insert(session_id, todos);
             }
+            Ok(_) => {}
+            Err(e) => skip_or_fail(strict, "todo", &path, e)?,
         }
     }
     Ok(by_session)
@@ -245,7 +639,147 @@ fn load_todos(dir: &Path) -> Result<HashMap<String, Vec<TodoEntry>>> {
 
 // ── Helpers ─────────────────────────────────────────────────────────
 
-fn load_json<T: serde::de::DeserializeOwned>(path: &Path) -> Result<T> {
-    let data = fs::read_to_string(path).with_context(|| format!("reading {}", path.display()))?;
+/// Whether a directory or file named `name` is read: always without a restriction,
+/// otherwise only when `only` lists it.
+fn wanted(only: Option<&HashSet<String>>, name: &str) -> bool {
+    only.is_none_or(|ids| ids.contains(name))
+}
+
+/// Keep one entry per ID, for stores where sync or corruption left the same message or
+/// part in several files. The copy with the highest `finished` time wins (any time
+/// beats none); on a tie the first one read does.
+fn dedupe_by_id<T>(
+    items: Vec<T>,
+    entity: &str,
+    dir: &Path,
+    id: impl Fn(&T) -> &String,
+    finished: impl Fn(&T) -> Option<u64>,
+) -> Vec<T> {
+    let mut out: Vec<T> = Vec::with_capacity(items.len());
+    let mut index: HashMap<String, usize> = HashMap::new();
+    for item in items {
+        match index.get(id(&item)) {
+            Some(&i) => {
+                eprintln!(
+                    "warn: duplicate {} {} in {:?}, keeping one copy",
+                    entity,
+                    id(&item),
+                    dir
+                );
+                if finished(&item) > finished(&out[i]) {
+                    out[i] = item;
+                }
+            }
+            None => {
+                index.insert(id(&item).clone(), out.len());
+                out.push(item);
+            }
+        }
+    }
+    out
+}
+
+/// Session counts per opencode version outside `Session::supported_versions()`, by version.
+pub fn unsupported_versions<'a>(
+    sessions: impl Iterator<Item = &'a Session>,
+) -> BTreeMap<&'a str, usize> {
+    let mut counts = BTreeMap::new();
+    for session in sessions {
+        if session.version_supported() == Some(false) {
+            if let Some(ref version) = session.version {
+                *counts.entry(version.as_str()).or_insert(0) += 1;
+            }
+        }
+    }
+    counts
+}
+
+/// When a part finished: its `time.end`, or a tool's `state.time.end`.
+fn part_end(part: &Part) -> Option<u64> {
+    let time = match &part.kind {
+        PartKind::Text { time, .. } | PartKind::Reasoning { time, .. } => time.as_ref(),
+        PartKind::Tool { state, .. } => state.time.as_ref(),
+        _ => None,
+    };
+    time.and_then(|t| t.end)
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name().unwrap().to_string_lossy().to_string()
+}
+
+fn file_stem(path: &Path) -> String {
+    path.file_stem().unwrap().to_string_lossy().to_string()
+}
+
+/// Warn and carry on, or under strict loading fail on the offending file.
+fn skip_or_fail(strict: bool, entity: &str, path: &Path, err: anyhow::Error) -> Result<()> {
+    if strict {
+        return Err(err.context(format!("invalid {} {:?} (strict load)", entity, path)));
+    }
+    eprintln!("warn: skipping {} {:?}: {}", entity, path, err);
+    Ok(())
+}
+
+/// Read and parse one JSON file. Invalid UTF-8 is replaced (with a warning) rather than
+/// losing the whole file, except under strict loading, where it is an error.
+fn load_json<T: serde::de::DeserializeOwned>(
+    storage: &Storage,
+    path: &Path,
+    strict: bool,
+) -> Result<T> {
+    let bytes = storage.read(path)?;
+    // Tool output can capture binary bytes
+    let data = match String::from_utf8(bytes) {
+        Ok(data) => data,
+        Err(err) if strict => {
+            return Err(err.utf8_error()).with_context(|| format!("reading {}", path.display()));
+        }
+        Err(err) => {
+            eprintln!(
+                "warn: invalid UTF-8 in {:?}, replacing bad bytes with U+FFFD",
+                path
+            );
+            String::from_utf8_lossy(err.as_bytes()).into_owned()
+        }
+    };
     serde_json::from_str(&data).with_context(|| format!("parsing {}", path.display()))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A storage dir holding one part whose text has an invalid byte (0xFF).
+    fn bad_utf8_storage(name: &str) -> (PathBuf, PathBuf) {
+        let dir = std::env::temp_dir().join(format!("oc-export-{}-{}", name, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("prt_bad.json");
+        let mut bytes =
+            br#"{"id":"prt_bad","sessionID":"ses_a","messageID":"msg_a","type":"text","text":"ok "#
+                .to_vec();
+        bytes.push(0xFF);
+        bytes.extend_from_slice(br#" ok"}"#);
+        fs::write(&path, bytes).unwrap();
+        (dir, path)
+    }
+
+    #[test]
+    fn invalid_utf8_is_replaced() {
+        let (dir, path) = bad_utf8_storage("lossy");
+        let part: Part = load_json(&Storage::open(&dir).unwrap(), &path, false).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        match part.kind {
+            PartKind::Text { text, .. } => assert_eq!(text, "ok \u{FFFD} ok"),
+            other => panic!("expected a text part, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn invalid_utf8_fails_strict_loading() {
+        let (dir, path) = bad_utf8_storage("strict");
+        let result: Result<Part> = load_json(&Storage::open(&dir).unwrap(), &path, true);
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(result.is_err());
+    }
+}
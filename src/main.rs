@@ -1,14 +1,31 @@
-mod loader;
-mod renderer;
-mod resolver;
-mod types;
-
-use anyhow::{bail, Result};
-use chrono::NaiveDate;
-use clap::Parser;
+use anyhow::{anyhow, bail, Context, Result};
+use chrono::{DateTime, Duration, NaiveDate, NaiveDateTime, Utc};
+use clap::{Parser, ValueEnum};
+use flate2::write::GzEncoder;
+use flate2::Compression;
 use indicatif::{ProgressBar, ProgressStyle};
-use std::fs;
-use std::path::PathBuf;
+use notify::{RecursiveMode, Watcher};
+use regex::Regex;
+use sha2::{Digest, Sha256};
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::time::Duration as StdDuration;
+
+use oc_export::chat::ChatSubAgents;
+use oc_export::events::EventSink;
+use oc_export::renderer::{
+    Anonymize, EmptyMessages, FenceStyle, ModelLabelStyle, NumberFormat, RenderOptions, Section,
+    TodoSort, TokenScope,
+};
+use oc_export::resolver::Filters;
+use oc_export::types::{
+    one_line, to_datetime, to_millis, PartKind, Project, ResolvedConversationItem, Session,
+};
+use oc_export::{chat, extract, loader, renderer, resolver, sqlite, tree, validate};
 
 #[derive(Parser, Debug)]
 #[command(
@@ -25,25 +42,741 @@ struct Cli {
     #[arg(long)]
     project: Option<String>,
 
+    /// Filter to the project with exactly this ID (no fuzzy matching)
+    #[arg(long, value_name = "ID", conflicts_with = "project")]
+    project_id: Option<String>,
+
     /// Export a single session by ID
     #[arg(long)]
     session: Option<String>,
 
-    /// Output directory
-    #[arg(long, short, default_value = "./opencode-export")]
-    output: PathBuf,
+    /// Export every session whose ID, slug, or title matches this regex
+    #[arg(long, value_name = "PATTERN", conflicts_with = "session")]
+    session_regex: Option<String>,
+
+    /// Output directory; `{year}`, `{month}`, `{day}` are filled from each session's creation date.
+    /// With `--format sqlite`, the database file [default: ./opencode-export.db]
+    #[arg(long, short)]
+    output: Option<PathBuf>,
+
+    /// Write a gzipped tarball at --output instead of a directory (implied by a `.tar.gz` or `.tgz` --output)
+    #[arg(long, default_value_t = false)]
+    archive: bool,
+
+    /// Output format
+    #[arg(long, value_enum, default_value_t = OutputFormat::Markdown)]
+    format: OutputFormat,
 
-    /// Only export sessions created after this date (YYYY-MM-DD)
+    /// With --format chat-json, nest each sub-agent's messages under its `task` result or flatten them
+    #[arg(long, value_enum, default_value_t = ChatSubAgents::Nest)]
+    chat_subagents: ChatSubAgents,
+
+    /// Only export sessions created on or after this date (YYYY-MM-DD) or age (7d, 2w, 1mo, 24h)
     #[arg(long)]
     since: Option<String>,
 
+    /// Only export sessions created on or before this date (YYYY-MM-DD) or before this age ago
+    #[arg(long)]
+    until: Option<String>,
+
+    /// Only export sessions created strictly after this session (a cursor for incremental runs)
+    #[arg(long, value_name = "ID")]
+    after_session: Option<String>,
+
+    /// Only export sessions created strictly before this session
+    #[arg(long, value_name = "ID")]
+    before_session: Option<String>,
+
+    /// Only export sessions with at least one failed tool call (sub-agents included)
+    #[arg(long, default_value_t = false)]
+    only_errors: bool,
+
+    /// Only export sessions that changed files (recorded diffs or a nonzero summary file count)
+    #[arg(long, default_value_t = false)]
+    only_with_diffs: bool,
+
+    /// Only export sessions where an assistant message used this agent mode (e.g. plan, build)
+    #[arg(long)]
+    mode: Option<String>,
+
+    /// Only export sessions that never spawned a sub-agent
+    #[arg(long, default_value_t = false)]
+    leaf_only: bool,
+
     /// Path to the opencode storage directory (auto-detected by default)
     #[arg(long)]
     storage: Option<PathBuf>,
 
+    /// Fail on the first unreadable or malformed storage file instead of skipping it
+    #[arg(long, default_value_t = false)]
+    strict_load: bool,
+
     /// List available projects and exit
     #[arg(long, default_value_t = false)]
     list: bool,
+
+    /// With --list, also show each top-level session's date, title, and first prompt
+    #[arg(long, default_value_t = false, requires = "list")]
+    detailed: bool,
+
+    /// With --list, hide projects with fewer than N sessions
+    #[arg(long, value_name = "N", default_value_t = 0, requires = "list")]
+    min_sessions: usize,
+
+    /// List top-level sessions (all, or --project's) with date, model, tokens, and sub-agents, and
+    /// exit; with --format json as a JSON array
+    #[arg(
+        long,
+        default_value_t = false,
+        conflicts_with_all = ["list", "validate", "watch"]
+    )]
+    list_sessions: bool,
+
+    /// Append parts whose message is missing to their session under "Orphaned Parts"
+    #[arg(long, default_value_t = false)]
+    render_orphans: bool,
+
+    /// Check storage for broken cross-references and exit non-zero if any are found
+    #[arg(long, default_value_t = false)]
+    validate: bool,
+
+    /// How to render messages with no content: placeholder or skip
+    #[arg(long, value_enum, default_value_t = EmptyMessages::Placeholder)]
+    empty_messages: EmptyMessages,
+
+    /// Replace the home directory prefix in all rendered paths with `~`
+    #[arg(long, default_value_t = false)]
+    anonymize: bool,
+
+    /// With --anonymize, also replace project names and worktrees with `project-N`
+    #[arg(long, default_value_t = false, requires = "anonymize")]
+    anonymize_projects: bool,
+
+    /// Message roles to include (comma-separated)
+    #[arg(
+        long,
+        value_delimiter = ',',
+        value_parser = ["user", "assistant"],
+        default_values = ["user", "assistant"]
+    )]
+    roles: Vec<String>,
+
+    /// Keep role headings and separators even when only one role is included
+    #[arg(long, default_value_t = false)]
+    keep_role_headers: bool,
+
+    /// Render each turn as a single line (`[U]`, `[A]`, `[T:tool]`) instead of full Markdown
+    #[arg(long, default_value_t = false)]
+    compact: bool,
+
+    /// Instead of exporting, write each assistant code block and written/edited file to
+    /// <DIR>/<ext>/<session>-<n>.<ext>, skipping duplicates
+    #[arg(long, value_name = "DIR", conflicts_with_all = ["prompt_only", "compact"])]
+    extract_code: Option<PathBuf>,
+
+    /// Write only the text of your own prompts (user messages), separated by `---` lines
+    #[arg(long, default_value_t = false, conflicts_with = "compact")]
+    prompt_only: bool,
+
+    /// Leave out the conversation: write only the header, task list, files changed, and token usage
+    #[arg(long, default_value_t = false, conflicts_with_all = ["compact", "prompt_only"])]
+    summary_only: bool,
+
+    /// With --prompt-only, drop prompts already written earlier in this run
+    #[arg(long, default_value_t = false, requires = "prompt_only")]
+    dedupe_prompts: bool,
+
+    /// Suppress progress output and the final summary (warnings still print)
+    #[arg(long, short, default_value_t = false)]
+    quiet: bool,
+
+    /// After exporting, keep watching the storage directory and re-export sessions as they change
+    #[arg(
+        long,
+        default_value_t = false,
+        conflicts_with_all = [
+            "list", "validate", "extract_code", "tree", "events_file", "diff_against",
+            "checksums", "resolve_only"
+        ]
+    )]
+    watch: bool,
+
+    /// Add input/output/reasoning proportion bars to the Token Usage section
+    #[arg(long, default_value_t = false)]
+    token_bars: bool,
+
+    /// Add a Tool Usage section counting calls per tool (sub-agent calls counted separately)
+    #[arg(long, default_value_t = false)]
+    tool_stats: bool,
+
+    /// Also write a `.patch` file per session with unified diffs of its changed files
+    #[arg(long, default_value_t = false)]
+    export_patches: bool,
+
+    /// Also write a `SHA256SUMS` file at the output root listing every written file's hash
+    #[arg(long, default_value_t = false)]
+    checksums: bool,
+
+    /// Don't derive titles and filenames from the first user message for untitled sessions
+    #[arg(long, default_value_t = false)]
+    no_derived_title: bool,
+
+    /// Render sub-agent turns at normal heading level, labelled `(sub-agent: name)`, instead of nested in blockquotes
+    #[arg(long, default_value_t = false)]
+    flatten_subagents: bool,
+
+    /// Render consecutive assistant messages with the same model as a single turn
+    #[arg(long, visible_alias = "merge-consecutive", default_value_t = false)]
+    merge_consecutive_assistant: bool,
+
+    /// Fold each assistant message's tool calls into one collapsible block (`task` calls stay visible)
+    #[arg(long, default_value_t = false)]
+    collapse_tools: bool,
+
+    /// Append each tool call's ID to its heading, for matching against opencode logs
+    #[arg(long, default_value_t = false)]
+    show_call_ids: bool,
+
+    /// Show every tool's full input as JSON instead of the curated per-tool view
+    #[arg(long, default_value_t = false)]
+    raw_tool_input: bool,
+
+    /// Collapse runs of three or more blank lines (outside code blocks) to two
+    #[arg(long, default_value_t = false)]
+    trim_empty_lines: bool,
+
+    /// Remove long base64-like signature blobs from reasoning text, keeping the prose
+    #[arg(long, default_value_t = false)]
+    strip_reasoning_signatures: bool,
+
+    /// Show tool and reasoning `metadata` (exit codes, line ranges, ...) as JSON under each part
+    #[arg(long, default_value_t = false)]
+    show_metadata: bool,
+
+    /// Truncate lines in tool input/output code blocks to this many columns, ending in `…` (0 = off)
+    #[arg(long, value_name = "COLS", default_value_t = 0)]
+    clamp_width: usize,
+
+    /// Code fence style; `auto` switches to tildes for blocks that contain triple backticks
+    #[arg(long, value_enum, default_value_t = FenceStyle::Backtick)]
+    fence: FenceStyle,
+
+    /// Bold this term wherever it appears in message text, outside code and URLs (repeatable)
+    #[arg(long, value_name = "TERM")]
+    highlight: Vec<String>,
+
+    /// End each file with a `<!-- oc-export: <in> in / <out> out / $<cost> / <n> files -->` line
+    #[arg(long, default_value_t = false)]
+    footer: bool,
+
+    /// Sections to render, in order (comma-separated); sections left out are not rendered
+    #[arg(
+        long,
+        value_enum,
+        value_delimiter = ',',
+        value_name = "SECTIONS",
+        default_values = ["header", "conversation", "todos", "diffs", "tools", "tokens"]
+    )]
+    section_order: Vec<Section>,
+
+    /// Task List order: as stored, sorted by status then priority, or also grouped by status
+    #[arg(long, value_enum, default_value_t = TodoSort::None)]
+    todo_sort: TodoSort,
+
+    /// Link read/write/edit file paths to `file://` URLs (relative paths resolve against the project worktree)
+    #[arg(long, default_value_t = false)]
+    link_files: bool,
+
+    /// Shift every heading down this many levels (`##` becomes `###` at 1), for embedding exports
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    heading_offset: usize,
+
+    /// Number step annotations and add running output-token and cost totals to each
+    #[arg(long, default_value_t = false)]
+    cumulative_steps: bool,
+
+    /// Show write calls as a diff against the file's earlier content in the session, when known
+    #[arg(long, default_value_t = false, conflicts_with = "reverse_messages")]
+    diff_writes: bool,
+
+    /// Bold a one-line summary of multi-line tool errors and collapse the full trace
+    #[arg(long, default_value_t = false)]
+    pretty_errors: bool,
+
+    /// Prefix each turn heading with its time since the session was created, e.g. [+2m30s]
+    #[arg(long, default_value_t = false)]
+    relative_times: bool,
+
+    /// Add a key under the Task List and Files Changed explaining their markers and counts
+    #[arg(long, default_value_t = false)]
+    legend: bool,
+
+    /// How to write token counts in the Token Usage table and the run summary
+    #[arg(long, value_enum, default_value_t = NumberFormat::Abbrev)]
+    number_format: NumberFormat,
+
+    /// Count only the session's own tokens and cost, or include its sub-agents'
+    #[arg(long, value_enum, default_value_t = TokenScope::Own)]
+    token_scope: TokenScope,
+
+    /// How to name models in assistant headings and the header
+    #[arg(long, value_enum, default_value_t = ModelLabelStyle::Model)]
+    model_label_style: ModelLabelStyle,
+
+    /// Add an "open in opencode" link (`<scheme>://session/<id>`) to the header
+    #[arg(long, default_value_t = false)]
+    deep_link: bool,
+
+    /// URI scheme for --deep-link
+    #[arg(
+        long,
+        value_name = "SCHEME",
+        default_value = "opencode",
+        requires = "deep_link"
+    )]
+    deep_link_scheme: String,
+
+    /// Export only the first N conversation items of each session (a sub-agent counts as one)
+    #[arg(long, value_name = "N")]
+    head: Option<usize>,
+
+    /// Within each session, export only conversation items created at or after this time
+    #[arg(long, value_name = "WHEN")]
+    message_since: Option<String>,
+
+    /// Within each session, export only conversation items created before this time
+    #[arg(long, value_name = "WHEN")]
+    message_until: Option<String>,
+
+    /// Add a Contents section after the header linking to each turn
+    #[arg(long, default_value_t = false)]
+    toc: bool,
+
+    /// Render each session newest message first (sub-agents stay after the message that spawned them)
+    #[arg(long, default_value_t = false)]
+    reverse_messages: bool,
+
+    /// Print one NDJSON event per session, message, and written file to stdout
+    #[arg(long, default_value_t = false)]
+    emit_events: bool,
+
+    /// Write the NDJSON event stream to this file instead of stdout (implies --emit-events)
+    #[arg(long)]
+    events_file: Option<PathBuf>,
+
+    /// Compare the written files with a previous export in DIR and list new, changed, and removed ones
+    #[arg(long, value_name = "DIR", conflicts_with = "extract_code")]
+    diff_against: Option<PathBuf>,
+
+    /// Also write a JSON outline of the exported projects and sessions (counts only, no content)
+    #[arg(long, value_name = "PATH")]
+    tree: Option<PathBuf>,
+
+    /// Print the resolved projects and sessions as Rust debug output to stdout and write nothing
+    #[arg(long, default_value_t = false)]
+    resolve_only: bool,
+
+    /// What to do when two sessions map to the same output file
+    #[arg(long, value_enum, default_value_t = OnCollision::Suffix)]
+    on_collision: OnCollision,
+
+    /// How to name each project's output folder
+    #[arg(long, value_enum, default_value_t = ProjectDirStyle::Name)]
+    project_dir_style: ProjectDirStyle,
+
+    /// Characters besides letters and digits to keep in output filenames; others become `-`
+    #[arg(
+        long,
+        value_name = "CHARS",
+        default_value = "-_",
+        value_parser = parse_filename_allow,
+        allow_hyphen_values = true
+    )]
+    filename_allow: String,
+
+    /// File extension for exported files (alphanumeric, without the dot)
+    /// [default: md; txt with --format text, json with --format chat-json]
+    #[arg(long, value_parser = parse_extension)]
+    extension: Option<String>,
+
+    /// Character encoding of exported session files
+    #[arg(long, value_enum, default_value_t = Encoding::Utf8)]
+    encoding: Encoding,
+
+    /// Start each exported session file with a byte order mark (always on with utf16le)
+    #[arg(long, default_value_t = false)]
+    bom: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum OutputFormat {
+    /// One Markdown file per session
+    Markdown,
+    /// Normalized tables in a single SQLite database
+    Sqlite,
+    /// One plain-text file per session, without Markdown syntax
+    Text,
+    /// One OpenAI-style chat `messages` JSON array per session
+    ChatJson,
+    /// A JSON array of sessions (only with --list-sessions)
+    Json,
+}
+
+/// `--output` tarball writer.
+type TarGz = tar::Builder<GzEncoder<BufWriter<File>>>;
+
+/// Session files sorted by how they differ from a `--diff-against` export, as paths
+/// relative to the export root.
+#[derive(Debug, Default)]
+struct ExportDiff {
+    new: Vec<PathBuf>,
+    changed: Vec<PathBuf>,
+    removed: Vec<PathBuf>,
+    unchanged: usize,
+}
+
+/// How to handle two sessions that produce the same output filename.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum OnCollision {
+    /// Append `-2`, `-3`, ... to the later session's filename
+    Suffix,
+    /// Let the later session replace the earlier file
+    Overwrite,
+    /// Keep the earlier file and don't export the later session
+    Skip,
+    /// Abort the export
+    Error,
+}
+
+/// Character encoding for `--encoding`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Encoding {
+    /// UTF-8, with a BOM only when `--bom` is given
+    Utf8,
+    /// UTF-16 little-endian, with a BOM, for older Windows tools
+    Utf16le,
+}
+
+/// `text` as the bytes of a session file under `--encoding` and `--bom`.
+fn encode_output(text: &str, encoding: Encoding, bom: bool) -> Cow<'_, [u8]> {
+    match encoding {
+        Encoding::Utf8 if !bom => Cow::Borrowed(text.as_bytes()),
+        Encoding::Utf8 => {
+            let mut out = Vec::with_capacity(text.len() + 3);
+            out.extend_from_slice(&[0xEF, 0xBB, 0xBF]);
+            out.extend_from_slice(text.as_bytes());
+            Cow::Owned(out)
+        }
+        // A UTF-16 file without a BOM can't be told from UTF-8 or big-endian, so it
+        // always gets one
+        Encoding::Utf16le => {
+            let mut out = Vec::with_capacity(text.len() * 2 + 2);
+            out.extend_from_slice(&[0xFF, 0xFE]);
+            for unit in text.encode_utf16() {
+                out.extend_from_slice(&unit.to_le_bytes());
+            }
+            Cow::Owned(out)
+        }
+    }
+}
+
+/// How to name the per-project output folder.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum ProjectDirStyle {
+    /// Last component of the worktree path
+    Name,
+    /// The whole worktree path, with path separators replaced by `__`
+    FullPath,
+    /// The project ID
+    Id,
+}
+
+/// Output folder name for `project` under `--project-dir-style`. With `anonymize`, a
+/// `full-path` name leaves out the home directory.
+fn project_dir_name(project: &Project, style: ProjectDirStyle, anonymize: bool) -> String {
+    match style {
+        ProjectDirStyle::Name => project.display_name(),
+        ProjectDirStyle::FullPath => {
+            let worktree = if anonymize {
+                let anon = Anonymize {
+                    home: loader::home_dir(),
+                    project_label: None,
+                };
+                // A leading `~` would read as a user's home in a shell
+                anon.apply(&project.worktree, project)
+                    .trim_start_matches('~')
+                    .to_string()
+            } else {
+                project.worktree.clone()
+            };
+            let path = worktree
+                .trim_matches(|c| c == '/' || c == '\\')
+                .replace(['/', '\\'], "__")
+                .replace(':', "");
+            if path.is_empty() {
+                project.display_name()
+            } else {
+                path
+            }
+        }
+        ProjectDirStyle::Id => project.id.clone(),
+    }
+}
+
+/// Turn a `--since`/`--until` value into an epoch-ms cutoff.
+///
+/// Relative ages (`24h`, `7d`, `2w`, `1mo`, `1y`) count back from now. Absolute
+/// `YYYY-MM-DD` dates mean the start of that day, or with `end_of_day` the start
+/// of the next one, so `--until 2025-01-31` still includes the 31st. A time of day
+/// (`2025-01-31T14:05`, seconds and an RFC 3339 offset optional; UTC otherwise) or a
+/// bare epoch timestamp is taken as that exact instant.
+fn parse_cutoff(flag: &str, value: &str, end_of_day: bool) -> Result<u64> {
+    let value = value.trim();
+    if !value.is_empty() && value.bytes().all(|b| b.is_ascii_digit()) && value.len() > 8 {
+        if let Ok(ts) = value.parse::<u64>() {
+            return Ok(to_millis(ts));
+        }
+    }
+    if let Ok(dt) = DateTime::parse_from_rfc3339(value) {
+        return Ok(dt.timestamp_millis().max(0) as u64);
+    }
+    for format in [
+        "%Y-%m-%dT%H:%M:%S",
+        "%Y-%m-%d %H:%M:%S",
+        "%Y-%m-%dT%H:%M",
+        "%Y-%m-%d %H:%M",
+    ] {
+        if let Ok(dt) = NaiveDateTime::parse_from_str(value, format) {
+            return Ok(dt.and_utc().timestamp_millis().max(0) as u64);
+        }
+    }
+    let split = value
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(value.len());
+    let (num, unit) = value.split_at(split);
+    if !num.is_empty() && !unit.is_empty() {
+        let hours = match unit {
+            "h" => Some(1),
+            "d" => Some(24),
+            "w" => Some(24 * 7),
+            "mo" => Some(24 * 30),
+            "y" => Some(24 * 365),
+            _ => None,
+        };
+        if let (Some(hours), Ok(n)) = (hours, num.parse::<i64>()) {
+            let cutoff = Utc::now() - Duration::hours(n.saturating_mul(hours));
+            return Ok(cutoff.timestamp_millis().max(0) as u64);
+        }
+    }
+
+    let date = NaiveDate::parse_from_str(value, "%Y-%m-%d").map_err(|_| {
+        anyhow!(
+            "Invalid {} value '{}' (expected YYYY-MM-DD, YYYY-MM-DDTHH:MM, an epoch \
+             timestamp, or an age like 24h, 7d, 2w, 1mo, 1y)",
+            flag,
+            value
+        )
+    })?;
+    let day = if end_of_day {
+        date.succ_opt().unwrap_or(date)
+    } else {
+        date
+    };
+    let dt = day.and_hms_opt(0, 0, 0).unwrap().and_utc();
+    Ok(dt.timestamp_millis().max(0) as u64)
+}
+
+/// Accept `md`, `.mdx`, `txt`, ... but nothing that could change the path.
+fn parse_extension(s: &str) -> Result<String, String> {
+    let ext = s.strip_prefix('.').unwrap_or(s);
+    if !ext.is_empty() && ext.chars().all(|c| c.is_ascii_alphanumeric()) {
+        Ok(ext.to_string())
+    } else {
+        Err(format!(
+            "'{}' is not a valid extension (letters and digits only)",
+            s
+        ))
+    }
+}
+
+fn parse_filename_allow(s: &str) -> Result<String, String> {
+    match s
+        .chars()
+        .find(|c| matches!(c, '/' | '\\' | '\0') || c.is_control())
+    {
+        Some(c) => Err(format!("{:?} can't appear in a filename", c)),
+        None => Ok(s.to_string()),
+    }
+}
+
+/// `--list --detailed` rows for a project's top-level sessions, oldest first.
+fn print_session_details(data: &loader::StorageData, project_id: &str) {
+    let mut sessions: Vec<&Session> = data
+        .sessions_by_project
+        .get(project_id)
+        .into_iter()
+        .flatten()
+        .filter_map(|id| data.sessions.get(id))
+        .filter(|s| s.parent_id.is_none())
+        .collect();
+    sessions.sort_by_key(|s| s.time.created.unwrap_or(0));
+    for session in sessions {
+        let title = session
+            .title
+            .as_deref()
+            .or(session.slug.as_deref())
+            .unwrap_or("(untitled)");
+        let prompt = data
+            .messages_by_session
+            .get(&session.id)
+            .into_iter()
+            .flatten()
+            .filter(|m| m.role == "user")
+            .find_map(|m| {
+                data.parts_by_message
+                    .get(&m.id)?
+                    .iter()
+                    .find_map(|p| match &p.kind {
+                        PartKind::Text { text, .. } if !text.trim().is_empty() => Some(text),
+                        _ => None,
+                    })
+            })
+            .map(|text| one_line(text, 60))
+            .unwrap_or_default();
+        println!(
+            "  {:<10}  {:<40}  {}",
+            date_str(session.time.created),
+            one_line(title, 37),
+            prompt
+        );
+    }
+}
+
+/// `--list-sessions`: every top-level session in `data`, oldest first.
+///
+/// Tokens are input + output + reasoning over the session's messages and those of all
+/// its sub-agents, which are counted at every depth.
+fn print_session_list(data: &loader::StorageData, json: bool) -> Result<()> {
+    let mut children: HashMap<&str, Vec<&str>> = HashMap::new();
+    for s in data.sessions.values() {
+        if let Some(ref pid) = s.parent_id {
+            children.entry(pid.as_str()).or_default().push(&s.id);
+        }
+    }
+    let project_names: HashMap<&str, String> = data
+        .projects
+        .iter()
+        .map(|p| (p.id.as_str(), p.display_name()))
+        .collect();
+    let mut sessions: Vec<&Session> = data
+        .sessions
+        .values()
+        .filter(|s| s.parent_id.is_none())
+        .collect();
+    sessions.sort_by_key(|s| (s.time.created.unwrap_or(0), &s.id));
+
+    let mut rows = Vec::new();
+    for session in sessions {
+        let mut sub_agents = Vec::new();
+        let mut stack = vec![session.id.as_str()];
+        while let Some(id) = stack.pop() {
+            for &child in children.get(id).into_iter().flatten() {
+                sub_agents.push(child);
+                stack.push(child);
+            }
+        }
+        let messages = |id: &str| data.messages_by_session.get(id).into_iter().flatten();
+        let tokens: u64 = std::iter::once(session.id.as_str())
+            .chain(sub_agents.iter().copied())
+            .flat_map(messages)
+            .filter_map(|m| m.tokens.as_ref())
+            .map(|t| t.total())
+            .sum();
+        let model = messages(&session.id)
+            .filter(|m| m.role == "assistant")
+            .find_map(|m| m.effective_model());
+        rows.push((session, model, tokens, sub_agents.len()));
+    }
+
+    if json {
+        let rows: Vec<_> = rows
+            .iter()
+            .map(|(session, model, tokens, sub_agents)| {
+                serde_json::json!({
+                    "id": session.id,
+                    "project_id": session.project_id,
+                    "project": project_names.get(session.project_id.as_str()),
+                    "created": session.time.created.map(to_millis),
+                    "title": session.title,
+                    "model": model,
+                    "tokens": tokens,
+                    "sub_agents": sub_agents,
+                })
+            })
+            .collect();
+        println!("{}", serde_json::to_string_pretty(&rows)?);
+        return Ok(());
+    }
+
+    println!(
+        "{:<30}  {:<10}  {:<12}  {:<24}  {:>8}  {:>4}  TITLE",
+        "ID", "DATE", "PROJECT", "MODEL", "TOKENS", "SUBS"
+    );
+    println!("{}", "-".repeat(110));
+    for (session, model, tokens, sub_agents) in &rows {
+        let title = session
+            .title
+            .as_deref()
+            .or(session.slug.as_deref())
+            .unwrap_or("(untitled)");
+        println!(
+            "{:<30}  {:<10}  {:<12}  {:<24}  {:>8}  {:>4}  {}",
+            session.id,
+            date_str(session.time.created),
+            project_names
+                .get(session.project_id.as_str())
+                .map_or("?", String::as_str),
+            one_line(model.unwrap_or("-"), 24),
+            renderer::format_number(*tokens),
+            sub_agents,
+            one_line(title, 40)
+        );
+    }
+    Ok(())
+}
+
+/// `YYYY-MM-DD` for output filenames, or `unknown`.
+fn date_str(created: Option<u64>) -> String {
+    match created {
+        Some(ts) => to_datetime(ts)
+            .unwrap_or_default()
+            .format("%Y-%m-%d")
+            .to_string(),
+        None => "unknown".to_string(),
+    }
+}
+
+/// Fill `{year}`, `{month}` and `{day}` in an `--output` template from a session's creation
+/// time. Sessions without a usable timestamp go under `unknown`.
+fn output_dir(template: &Path, created: Option<u64>) -> PathBuf {
+    let template = template.to_string_lossy();
+    if !template.contains('{') {
+        return PathBuf::from(template.as_ref());
+    }
+    let (year, month, day) = match created.and_then(to_datetime) {
+        Some(dt) => (
+            dt.format("%Y").to_string(),
+            dt.format("%m").to_string(),
+            dt.format("%d").to_string(),
+        ),
+        None => ("unknown".into(), "unknown".into(), "unknown".into()),
+    };
+    PathBuf::from(
+        template
+            .replace("{year}", &year)
+            .replace("{month}", &month)
+            .replace("{day}", &day),
+    )
 }
 
 fn main() -> Result<()> {
@@ -51,7 +784,8 @@ fn main() -> Result<()> {
 
     let storage_dir = cli
         .storage
-        .unwrap_or_else(|| loader::default_storage_path());
+        .clone()
+        .unwrap_or_else(loader::default_storage_path);
 
     if !storage_dir.exists() {
         bail!(
@@ -59,21 +793,123 @@ fn main() -> Result<()> {
             storage_dir.display()
         );
     }
+    if cli.watch && !storage_dir.is_dir() {
+        bail!("--watch needs a storage directory, not an archive");
+    }
+    if cli.watch && writes_archive(&cli) {
+        bail!("--watch needs a directory --output, not an archive");
+    }
+    let stdout_report = cli.list
+        || cli.list_sessions
+        || cli.validate
+        || cli.resolve_only
+        || cli.diff_against.is_some();
+    if cli.emit_events && cli.events_file.is_none() && stdout_report {
+        bail!(
+            "--list, --list-sessions, --validate, --resolve-only, and --diff-against print to \
+             stdout; send the event stream to --events-file instead"
+        );
+    }
+    if cli.diff_against.is_some() && cli.format == OutputFormat::Sqlite {
+        bail!("--diff-against compares session files and cannot be used with --format sqlite");
+    }
+    if cli.watch && cli.format == OutputFormat::Sqlite {
+        bail!("--watch re-exports session files and cannot update a --format sqlite database");
+    }
+    if matches!(cli.format, OutputFormat::Text | OutputFormat::ChatJson)
+        && (cli.compact || cli.prompt_only)
+    {
+        bail!("--format text and chat-json cannot be combined with --compact or --prompt-only");
+    }
+    if cli.list_sessions && !matches!(cli.format, OutputFormat::Markdown | OutputFormat::Json) {
+        bail!("--list-sessions prints a table, or JSON with --format json");
+    }
+    if cli.format == OutputFormat::Json && !cli.list_sessions {
+        bail!("--format json only applies to --list-sessions");
+    }
+    if cli.summary_only && cli.format != OutputFormat::Markdown {
+        bail!("--summary-only only applies to --format markdown");
+    }
 
+    export(&cli, &storage_dir, None)?;
+    if cli.watch {
+        watch(&cli, &storage_dir)?;
+    }
+    Ok(())
+}
+
+/// Load, filter, and export. With `changed` (storage IDs from `--watch`), only the
+/// top-level sessions those IDs belong to are exported, and finding none is not an error.
+fn export(cli: &Cli, storage_dir: &Path, changed: Option<&HashSet<String>>) -> Result<()> {
     // ── Load ────────────────────────────────────────────────────────
-    eprintln!("Loading data from {} ...", storage_dir.display());
+    if !cli.quiet {
+        eprintln!("Loading data from {} ...", storage_dir.display());
+    }
 
-    let data = loader::load_all(&storage_dir)?;
+    // --list and --validate look at every project, a session cursor may be in any
+    // project, and orphaned parts are only found by reading every part directory;
+    // otherwise --project's (or --project-id's) are enough
+    let only_project = (cli.project.is_some() || cli.project_id.is_some())
+        && !cli.list
+        && !cli.validate
+        && !cli.render_orphans
+        && cli.after_session.is_none()
+        && cli.before_session.is_none();
+    let keep_project = |p: &Project| {
+        cli.project
+            .as_deref()
+            .is_none_or(|f| resolver::project_matches(p, f))
+            && cli.project_id.as_deref().is_none_or(|id| p.id == id)
+    };
+    let data = if cli.list_sessions {
+        // Models and token counts come from messages; parts would only slow this down
+        loader::load_headers(storage_dir, cli.strict_load, keep_project)?
+    } else if cli.quiet {
+        if only_project {
+            loader::load_matching(storage_dir, cli.strict_load, keep_project)?
+        } else {
+            loader::load_all(storage_dir, cli.strict_load)?
+        }
+    } else {
+        let pb = ProgressBar::new(0);
+        pb.set_style(
+            ProgressStyle::default_bar()
+                .template("  [{bar:40.cyan/blue}] {pos}/{len} files")?
+                .progress_chars("=> "),
+        );
+        let on_file = |done: usize, total: usize| {
+            pb.set_length(total as u64);
+            pb.set_position(done as u64);
+        };
+        let data = if only_project {
+            loader::load_matching_with_progress(storage_dir, cli.strict_load, keep_project, on_file)
+        } else {
+            loader::load_all_with_progress(storage_dir, cli.strict_load, on_file)
+        };
+        pb.finish_and_clear();
+        data?
+    };
+    for (version, count) in loader::unsupported_versions(data.sessions.values()) {
+        eprintln!(
+            "warn: {} sessions written by opencode {}, outside the supported versions ({}); \
+             some parts may render as unknown",
+            count,
+            version,
+            Session::supported_versions_label()
+        );
+    }
 
-    eprintln!(
-        "  {} projects, {} sessions loaded",
-        data.projects.len(),
-        data.sessions.len()
-    );
+    if !cli.quiet {
+        eprintln!(
+            "  {} projects, {} sessions loaded",
+            data.projects.len(),
+            data.sessions.len()
+        );
+    }
 
     // ── List mode ───────────────────────────────────────────────────
     if cli.list {
-        println!("{:<12}  {:<40}  {}", "NAME", "WORKTREE", "SESSIONS");
+        println!("{:<12}  {:<40}  SESSIONS", "NAME", "WORKTREE");
         println!("{}", "-".repeat(80));
         for project in &data.projects {
             let name = project.display_name();
@@ -82,94 +918,887 @@ fn main() -> Result<()> {
                 .get(&project.id)
                 .map(|v| v.len())
                 .unwrap_or(0);
+            if count < cli.min_sessions {
+                continue;
+            }
             println!("{:<12}  {:<40}  {}", name, project.worktree, count);
+            if cli.detailed {
+                print_session_details(&data, &project.id);
+            }
         }
         return Ok(());
     }
 
-    // Must specify --all, --project, or --session
-    if !cli.all && cli.project.is_none() && cli.session.is_none() {
+    if cli.list_sessions {
+        return print_session_list(&data, cli.format == OutputFormat::Json);
+    }
+
+    if cli.validate {
+        let problems = validate::validate(&data);
+        for p in &problems {
+            println!("{:<19}  {}", p.check, p.detail);
+        }
+        if problems.is_empty() {
+            println!("No problems found");
+            return Ok(());
+        }
+        bail!("{} problems found", problems.len());
+    }
+
+    // Must specify --all, --project, --project-id, --session, or --session-regex
+    if !cli.all
+        && cli.project.is_none()
+        && cli.project_id.is_none()
+        && cli.session.is_none()
+        && cli.session_regex.is_none()
+    {
         bail!(
-            "Specify --all, --project <name>, or --session <id>.\n\
+            "Specify --all, --project <name>, --project-id <id>, --session <id>, or \
+             --session-regex <pattern>.\n\
              Use --list to see available projects."
         );
     }
 
-    // ── Parse --since ───────────────────────────────────────────────
-    let since_ms = match cli.since {
-        Some(ref date_str) => {
-            let date = NaiveDate::parse_from_str(date_str, "%Y-%m-%d").map_err(|e| {
-                anyhow::anyhow!(
-                    "Invalid --since date '{}': {} (expected YYYY-MM-DD)",
-                    date_str,
-                    e
-                )
-            })?;
-            let dt = date.and_hms_opt(0, 0, 0).unwrap().and_utc();
-            Some(dt.timestamp_millis() as u64)
-        }
-        None => None,
-    };
+    // ── Parse --since / --until ─────────────────────────────────────
+    let mut since_ms = cli
+        .since
+        .as_deref()
+        .map(|s| parse_cutoff("--since", s, false))
+        .transpose()?;
+    let mut until_ms = cli
+        .until
+        .as_deref()
+        .map(|s| parse_cutoff("--until", s, true))
+        .transpose()?;
+    // Session cursors narrow the same window; `until_ms` is already exclusive
+    if let Some(ref id) = cli.after_session {
+        let after = cursor_created(&data, "--after-session", id)? + 1;
+        since_ms = Some(since_ms.map_or(after, |s| s.max(after)));
+    }
+    if let Some(ref id) = cli.before_session {
+        let before = cursor_created(&data, "--before-session", id)?;
+        until_ms = Some(until_ms.map_or(before, |u| u.min(before)));
+    }
+
+    let session_regex = cli
+        .session_regex
+        .as_deref()
+        .map(|p| Regex::new(p).with_context(|| format!("invalid --session-regex {:?}", p)))
+        .transpose()?;
 
     // ── Resolve ─────────────────────────────────────────────────────
-    let resolved = resolver::resolve(
-        &data,
-        cli.project.as_deref(),
-        cli.session.as_deref(),
+    let filters = Filters {
+        project: cli.project.as_deref(),
+        project_id: cli.project_id.as_deref(),
+        session: cli.session.as_deref(),
+        session_regex: session_regex.as_ref(),
         since_ms,
-    );
+        until_ms,
+        mode: cli.mode.as_deref(),
+        leaf_only: cli.leaf_only,
+    };
+    let mut resolved = resolver::resolve(&data, &filters);
+
+    if let Some(ref pattern) = cli.session_regex {
+        if !cli.quiet {
+            let matched: usize = resolved.iter().map(|p| p.sessions.len()).sum();
+            eprintln!("  {} sessions match /{}/", matched, pattern);
+        }
+    }
+
+    if cli.only_errors {
+        for rp in &mut resolved {
+            rp.sessions.retain(|rs| rs.has_tool_errors());
+        }
+        resolved.retain(|rp| !rp.sessions.is_empty());
+        if !cli.quiet {
+            let matched: usize = resolved.iter().map(|p| p.sessions.len()).sum();
+            eprintln!("  {} sessions with tool errors", matched);
+        }
+    }
+
+    if cli.only_with_diffs {
+        let before: usize = resolved.iter().map(|p| p.sessions.len()).sum();
+        for rp in &mut resolved {
+            rp.sessions.retain(|rs| rs.has_file_changes());
+        }
+        resolved.retain(|rp| !rp.sessions.is_empty());
+        if !cli.quiet {
+            let matched: usize = resolved.iter().map(|p| p.sessions.len()).sum();
+            eprintln!(
+                "  {} sessions changed files ({} without changes skipped)",
+                matched,
+                before - matched
+            );
+        }
+    }
+
+    // A watch re-export writes only the changed sessions, but keeps the rest of their
+    // projects so that --on-collision names every file as the full export did
+    let roots = changed.map(|changed| changed_roots(&data, changed));
+    if let Some(ref roots) = roots {
+        resolved.retain(|rp| {
+            rp.sessions
+                .iter()
+                .any(|rs| roots.contains(rs.session.id.as_str()))
+        });
+        if resolved.is_empty() {
+            return Ok(());
+        }
+    }
 
     if resolved.is_empty() {
         bail!("No matching sessions found.");
     }
 
-    let total_sessions: usize = resolved.iter().map(|p| p.sessions.len()).sum();
-    eprintln!("Exporting {} sessions ...", total_sessions);
+    if cli.render_orphans {
+        let mut orphans = resolver::orphaned_parts(&data);
+        for rs in resolved.iter_mut().flat_map(|rp| rp.sessions.iter_mut()) {
+            rs.orphan_parts = orphans.remove(&rs.session.id).unwrap_or_default();
+        }
+    }
+
+    let message_since = cli
+        .message_since
+        .as_deref()
+        .map(|s| parse_cutoff("--message-since", s, false))
+        .transpose()?;
+    let message_until = cli
+        .message_until
+        .as_deref()
+        .map(|s| parse_cutoff("--message-until", s, true))
+        .transpose()?;
+    if message_since.is_some() || message_until.is_some() {
+        for rs in resolved.iter_mut().flat_map(|rp| rp.sessions.iter_mut()) {
+            // Items without a creation time stay
+            let created = |item: &ResolvedConversationItem| match item {
+                ResolvedConversationItem::Message(rm) => rm.message.time.created.map(to_millis),
+                ResolvedConversationItem::SubAgent { session, .. } => {
+                    session.time.created.map(to_millis)
+                }
+            };
+            let early = |item: &ResolvedConversationItem| {
+                created(item).zip(message_since).is_some_and(|(t, s)| t < s)
+            };
+            let late = |item: &ResolvedConversationItem| {
+                created(item)
+                    .zip(message_until)
+                    .is_some_and(|(t, u)| t >= u)
+            };
+            let before = rs.messages.iter().filter(|item| early(item)).count();
+            let after = rs.messages.iter().filter(|item| late(item)).count();
+            rs.messages.retain(|item| !early(item) && !late(item));
+            rs.omitted_before = before;
+            rs.omitted_items = after;
+        }
+    }
+
+    if let Some(n) = cli.head {
+        for rs in resolved.iter_mut().flat_map(|rp| rp.sessions.iter_mut()) {
+            rs.omitted_items += rs.messages.len().saturating_sub(n);
+            rs.messages.truncate(n);
+        }
+    }
+
+    if cli.reverse_messages {
+        for rs in resolved.iter_mut().flat_map(|rp| rp.sessions.iter_mut()) {
+            rs.messages = resolver::reverse_messages(std::mem::take(&mut rs.messages));
+        }
+    }
+
+    // Debugging aid for the resolver: the structure the renderers would get
+    if cli.resolve_only {
+        // Written rather than println!'d so that piping into `head` ends quietly
+        let mut out = std::io::stdout().lock();
+        if let Err(e) = writeln!(out, "{:#?}", resolved) {
+            if e.kind() != std::io::ErrorKind::BrokenPipe {
+                return Err(e).context("writing resolved sessions to stdout");
+            }
+        }
+        return Ok(());
+    }
+
+    let total_sessions: usize = resolved
+        .iter()
+        .flat_map(|p| &p.sessions)
+        .filter(|rs| {
+            roots
+                .as_ref()
+                .is_none_or(|roots| roots.contains(rs.session.id.as_str()))
+        })
+        .count();
+    if !cli.quiet {
+        eprintln!("Exporting {} sessions ...", total_sessions);
+    }
+
+    if let Some(ref path) = cli.tree {
+        tree::write(path, &resolved)?;
+    }
+
+    let mut events = match cli.events_file {
+        Some(ref path) => Some(EventSink::file(path)?),
+        None if cli.emit_events => Some(EventSink::stdout()),
+        None => None,
+    };
+
+    if cli.format == OutputFormat::Sqlite {
+        let db = cli
+            .output
+            .clone()
+            .unwrap_or_else(|| PathBuf::from("./opencode-export.db"));
+        let counts = sqlite::export(&db, &resolved)?;
+        if let Some(mut ev) = events {
+            for rp in &resolved {
+                for rs in &rp.sessions {
+                    ev.session(&rp.project, rs)?;
+                }
+            }
+            let bytes = fs::metadata(&db).map(|m| m.len()).unwrap_or(0);
+            ev.emit(
+                "file_written",
+                serde_json::json!({ "session": null, "path": db, "bytes": bytes }),
+            )?;
+            ev.finish()?;
+        }
+        if !cli.quiet {
+            eprintln!(
+                "\nWrote {} sessions, {} messages, {} parts to {}",
+                counts.sessions,
+                counts.messages,
+                counts.parts,
+                db.display()
+            );
+        }
+        return Ok(());
+    }
+
+    if let Some(ref dir) = cli.extract_code {
+        let mut seen: HashSet<String> = HashSet::new();
+        // Stems already given to a session, per project folder
+        let mut stems: HashSet<(String, String)> = HashSet::new();
+        let (mut extracted, mut duplicates) = (0, 0);
+        for (project_idx, rp) in resolved.iter().enumerate() {
+            for rs in &rp.sessions {
+                if let Some(ref mut ev) = events {
+                    ev.session(&rp.project, rs)?;
+                }
+                let project_name = if cli.anonymize_projects {
+                    format!("project-{}", project_idx + 1)
+                } else {
+                    project_dir_name(&rp.project, cli.project_dir_style, cli.anonymize)
+                };
+                let mut stem = rs.session.file_stem_allowing(
+                    &date_str(rs.session.time.created),
+                    (!cli.no_derived_title)
+                        .then(|| rs.derived_title())
+                        .flatten()
+                        .as_deref(),
+                    &cli.filename_allow,
+                );
+                // Two sessions with the same date and title would share snippet names
+                if !stems.insert((project_name.clone(), stem.clone())) {
+                    stem = format!("{}_{}", stem, rs.session.id);
+                    stems.insert((project_name.clone(), stem.clone()));
+                }
+                let mut n = 0;
+                for snippet in extract::code_snippets(&rs.messages) {
+                    if !seen.insert(snippet.code.clone()) {
+                        duplicates += 1;
+                        continue;
+                    }
+                    n += 1;
+                    let lang_dir = dir.join(&snippet.ext).join(&project_name);
+                    fs::create_dir_all(&lang_dir)?;
+                    let path = lang_dir.join(format!("{}-{}.{}", stem, n, snippet.ext));
+                    write_atomic(&path, snippet.code.as_bytes())?;
+                    if let Some(ref mut ev) = events {
+                        ev.emit(
+                            "file_written",
+                            serde_json::json!({
+                                "session": rs.session.id,
+                                "path": path,
+                                "bytes": snippet.code.len(),
+                            }),
+                        )?;
+                    }
+                    extracted += 1;
+                }
+            }
+        }
+        if let Some(ev) = events {
+            ev.finish()?;
+        }
+        if !cli.quiet {
+            eprintln!(
+                "\nWrote {} snippets to {} ({} duplicates skipped)",
+                extracted,
+                dir.display(),
+                duplicates
+            );
+        }
+        return Ok(());
+    }
+
+    let output = cli
+        .output
+        .clone()
+        .unwrap_or_else(|| PathBuf::from("./opencode-export"));
+    let mut tarball = if writes_archive(cli) {
+        if cli.diff_against.is_some() {
+            bail!("--diff-against needs a directory --output, not an archive");
+        }
+        // Renamed into place once finished, like the files of a directory export
+        let tmp = temp_path(&output);
+        let file = File::create(&tmp).with_context(|| format!("creating {}", tmp.display()))?;
+        Some(tar::Builder::new(GzEncoder::new(
+            BufWriter::new(file),
+            Compression::default(),
+        )))
+    } else {
+        None
+    };
+    // Paths inside an archive start at the project folder
+    let dir_template = match tarball {
+        Some(_) => PathBuf::new(),
+        None => output.clone(),
+    };
 
     // ── Render & write ──────────────────────────────────────────────
-    let pb = ProgressBar::new(total_sessions as u64);
+    let pb = if cli.quiet {
+        ProgressBar::hidden()
+    } else {
+        ProgressBar::new(total_sessions as u64)
+    };
     pb.set_style(
         ProgressStyle::default_bar()
             .template("  [{bar:40.cyan/blue}] {pos}/{len} {msg}")?
             .progress_chars("=> "),
     );
 
+    let mut roles = cli.roles.clone();
+    roles.sort();
+    roles.dedup();
+    let only_one_role = roles.len() == 1;
+    let mut section_order = cli.section_order.clone();
+    let mut seen_sections = HashSet::new();
+    section_order.retain(|s| seen_sections.insert(*s));
+    if cli.summary_only {
+        section_order.retain(|s| *s != Section::Conversation);
+    }
+    let render_opts = RenderOptions {
+        empty_messages: cli.empty_messages,
+        anonymize: cli.anonymize.then(|| Anonymize {
+            home: loader::home_dir(),
+            project_label: None,
+        }),
+        bare_turns: only_one_role && !cli.keep_role_headers,
+        roles: cli.roles.clone(),
+        token_bars: cli.token_bars,
+        derive_titles: !cli.no_derived_title,
+        flatten_subagents: cli.flatten_subagents,
+        merge_consecutive: cli.merge_consecutive_assistant,
+        highlight: cli.highlight.clone(),
+        show_metadata: cli.show_metadata,
+        clamp_width: cli.clamp_width,
+        tool_stats: cli.tool_stats,
+        raw_tool_input: cli.raw_tool_input,
+        fence: cli.fence,
+        deep_link: cli.deep_link.then(|| cli.deep_link_scheme.clone()),
+        model_label: cli.model_label_style,
+        collapse_tools: cli.collapse_tools,
+        number_format: cli.number_format,
+        token_scope: cli.token_scope,
+        toc: cli.toc,
+        show_call_ids: cli.show_call_ids,
+        footer: cli.footer,
+        strip_signatures: cli.strip_reasoning_signatures,
+        trim_empty_lines: cli.trim_empty_lines,
+        section_order,
+        todo_sort: cli.todo_sort,
+        link_files: None,
+        heading_offset: cli.heading_offset,
+        cumulative_steps: cli.cumulative_steps,
+        diff_writes: cli.diff_writes,
+        pretty_errors: cli.pretty_errors,
+        relative_times: cli.relative_times,
+        legend: cli.legend,
+    };
+
     let mut files_written = 0;
+    let mut sessions_exported = 0;
+    // Every session file written this run, for --on-collision
+    let mut written: HashSet<PathBuf> = HashSet::new();
+    let mut run_tokens = 0;
+    // Prompts written so far, for --dedupe-prompts
+    let mut seen_prompts: HashSet<String> = HashSet::new();
+    let mut run_cost = 0.0;
+    let extension = cli.extension.as_deref().unwrap_or(match cli.format {
+        OutputFormat::Text => "txt",
+        OutputFormat::ChatJson => "json",
+        OutputFormat::Markdown | OutputFormat::Sqlite | OutputFormat::Json => "md",
+    });
+    // --diff-against compares paths below the first templated component of --output
+    let output_root: PathBuf = output
+        .components()
+        .take_while(|c| !c.as_os_str().to_string_lossy().contains('{'))
+        .collect();
+    let mut diff = ExportDiff::default();
+    // (relative path, SHA-256) of every written file, for --checksums
+    let mut checksums: Option<Vec<(PathBuf, String)>> = cli.checksums.then(Vec::new);
 
-    for rp in &resolved {
-        let project_name = rp.project.display_name();
-        let project_dir = cli.output.join(&project_name);
-        fs::create_dir_all(&project_dir)?;
+    for (project_idx, rp) in resolved.iter().enumerate() {
+        let mut render_opts = render_opts.clone();
+        if cli.link_files {
+            render_opts.link_files = Some(rp.project.worktree.clone());
+        }
+        let project_name = if cli.anonymize_projects {
+            let label = format!("project-{}", project_idx + 1);
+            if let Some(ref mut anon) = render_opts.anonymize {
+                anon.project_label = Some(label.clone());
+            }
+            label
+        } else {
+            project_dir_name(&rp.project, cli.project_dir_style, cli.anonymize)
+        };
 
         for rs in &rp.sessions {
-            let date_str = match rs.session.time.created {
-                Some(ms) => {
-                    let secs = (ms / 1000) as i64;
-                    let dt = chrono::DateTime::from_timestamp(secs, 0).unwrap_or_default();
-                    dt.format("%Y-%m-%d").to_string()
+            let project_dir =
+                output_dir(&dir_template, rs.session.time.created).join(&project_name);
+            if tarball.is_none() {
+                fs::create_dir_all(&project_dir)?;
+            }
+
+            let date_str = date_str(rs.session.time.created);
+            let rewrite = roots
+                .as_ref()
+                .is_none_or(|roots| roots.contains(rs.session.id.as_str()));
+
+            let derived = render_opts
+                .derive_titles
+                .then(|| rs.derived_title())
+                .flatten();
+            let base_stem =
+                rs.session
+                    .file_stem_allowing(&date_str, derived.as_deref(), &cli.filename_allow);
+            let mut stem = base_stem.clone();
+            if written.contains(&project_dir.join(format!("{}.{}", stem, extension))) {
+                match cli.on_collision {
+                    OnCollision::Overwrite => {}
+                    OnCollision::Skip => {
+                        if rewrite {
+                            pb.inc(1);
+                        }
+                        continue;
+                    }
+                    OnCollision::Error => bail!(
+                        "Session {} would overwrite {}.{} in {} (see --on-collision)",
+                        rs.session.id,
+                        stem,
+                        extension,
+                        project_dir.display()
+                    ),
+                    OnCollision::Suffix => {
+                        let mut n = 2;
+                        while written.contains(&project_dir.join(format!("{}.{}", stem, extension)))
+                        {
+                            stem = format!("{}-{}", base_stem, n);
+                            n += 1;
+                        }
+                    }
                 }
-                None => "unknown".to_string(),
+            }
+            let filename = format!("{}.{}", stem, extension);
+            if !rewrite {
+                // Unchanged: its file stays, but the name is taken
+                written.insert(project_dir.join(&filename));
+                continue;
+            }
+            pb.set_message(format!("{}/{}", project_name, filename));
+
+            // Full Markdown is streamed to the file below; the other modes are small
+            let text = if cli.prompt_only {
+                let mut prompts = renderer::session_prompts(rs, &rp.project, &render_opts);
+                if cli.dedupe_prompts {
+                    prompts.retain(|p| seen_prompts.insert(p.clone()));
+                }
+                if prompts.is_empty() {
+                    pb.inc(1);
+                    continue;
+                }
+                Some(prompts.join("\n\n---\n\n") + "\n")
+            } else if cli.compact {
+                Some(renderer::render_session_compact(
+                    rs,
+                    &rp.project,
+                    &render_opts,
+                ))
+            } else if cli.format == OutputFormat::Text {
+                Some(renderer::render_session_text(rs, &rp.project, &render_opts))
+            } else if cli.format == OutputFormat::ChatJson {
+                let messages = chat::chat_messages(rs, cli.chat_subagents);
+                let json = serde_json::to_string_pretty(&messages)? + "\n";
+                Some(match render_opts.anonymize {
+                    Some(ref anon) => anon.apply(&json, &rp.project),
+                    None => json,
+                })
+            } else {
+                None
             };
 
-            let filename = format!("{}.md", rs.session.file_stem(&date_str));
-            pb.set_message(format!("{}/{}", project_name, filename));
+            // Re-encoded Markdown can't be streamed, so it is rendered up front
+            let text = match text {
+                None if cli.bom || cli.encoding != Encoding::Utf8 => {
+                    Some(renderer::render_session(rs, &rp.project, &render_opts))
+                }
+                text => text,
+            };
+            let data = text
+                .as_deref()
+                .map(|t| encode_output(t, cli.encoding, cli.bom));
 
-            let markdown = renderer::render_session(rs, &rp.project);
+            if let Some(ref mut ev) = events {
+                ev.session(&rp.project, rs)?;
+            }
 
             let filepath = project_dir.join(&filename);
-            fs::write(&filepath, &markdown)?;
+            let rel = filepath
+                .strip_prefix(&output_root)
+                .unwrap_or(&filepath)
+                .to_path_buf();
+            // Read before writing, so --diff-against may name the output directory itself
+            let previous = match cli.diff_against {
+                Some(ref old) => Some(read_if_exists(&old.join(&rel))?),
+                None => None,
+            };
+            let bytes = match (data, tarball.as_mut()) {
+                (Some(data), Some(tar)) => {
+                    append_file(tar, &filepath, &data)?;
+                    add_checksum(&mut checksums, &rel, &data);
+                    data.len() as u64
+                }
+                (None, Some(tar)) => {
+                    let mut buf = Vec::new();
+                    renderer::render_session_to_writer(&mut buf, rs, &rp.project, &render_opts)?;
+                    append_file(tar, &filepath, &buf)?;
+                    add_checksum(&mut checksums, &rel, &buf);
+                    buf.len() as u64
+                }
+                (Some(data), None) => {
+                    write_atomic(&filepath, &data)?;
+                    add_checksum(&mut checksums, &rel, &data);
+                    data.len() as u64
+                }
+                (None, None) => {
+                    let tmp = temp_path(&filepath);
+                    let file = File::create(&tmp)
+                        .with_context(|| format!("creating {}", tmp.display()))?;
+                    let mut out = BufWriter::new(file);
+                    let n =
+                        renderer::render_session_to_writer(&mut out, rs, &rp.project, &render_opts)
+                            .and_then(|n| out.flush().map(|_| n));
+                    // Closed first: Windows won't rename a file that is still open
+                    drop(out);
+                    let n = n
+                        .and_then(|n| fs::rename(&tmp, &filepath).map(|_| n))
+                        .inspect_err(|_| {
+                            let _ = fs::remove_file(&tmp);
+                        })
+                        .with_context(|| format!("writing {}", filepath.display()))?;
+                    // Streamed straight to disk, so hash what landed there
+                    if checksums.is_some() {
+                        let data = fs::read(&filepath)
+                            .with_context(|| format!("reading {}", filepath.display()))?;
+                        add_checksum(&mut checksums, &rel, &data);
+                    }
+                    n
+                }
+            };
+            if let Some(ref mut ev) = events {
+                ev.emit(
+                    "file_written",
+                    serde_json::json!({
+                        "session": rs.session.id,
+                        "path": filepath,
+                        "bytes": bytes,
+                    }),
+                )?;
+            }
+            match previous {
+                Some(None) => diff.new.push(rel),
+                Some(Some(old)) if old == fs::read(&filepath)? => diff.unchanged += 1,
+                Some(Some(_)) => diff.changed.push(rel),
+                None => {}
+            }
+            written.insert(filepath);
             files_written += 1;
+            sessions_exported += 1;
+
+            if cli.export_patches {
+                if let Some(patch) = renderer::render_patch(rs, &rp.project, &render_opts) {
+                    let path = project_dir.join(format!("{}.patch", stem));
+                    let rel = path.strip_prefix(&output_root).unwrap_or(&path);
+                    add_checksum(&mut checksums, rel, patch.as_bytes());
+                    match tarball.as_mut() {
+                        Some(tar) => append_file(tar, &path, patch.as_bytes())?,
+                        None => write_atomic(&path, patch.as_bytes())?,
+                    }
+                    files_written += 1;
+                }
+            }
+            match cli.token_scope {
+                TokenScope::Own => {
+                    run_tokens += rs.token_totals.total();
+                    run_cost += rs.cost_total;
+                }
+                TokenScope::Total => {
+                    let (tokens, cost) = rs.totals_with_sub_agents();
+                    run_tokens += tokens.total();
+                    run_cost += cost;
+                }
+            }
 
             pb.inc(1);
         }
     }
 
     pb.finish_with_message("done");
-    eprintln!(
-        "\nWrote {} files to {}",
-        files_written,
-        cli.output.display()
-    );
+    if let Some(mut sums) = checksums {
+        sums.sort();
+        let mut text = String::new();
+        for (path, hash) in &sums {
+            // Forward slashes, so `sha256sum -c` reads the file on any platform
+            let path = path.to_string_lossy().replace('\\', "/");
+            text.push_str(&format!("{}  {}\n", hash, path));
+        }
+        let path = output_root.join("SHA256SUMS");
+        match tarball.as_mut() {
+            Some(tar) => append_file(tar, Path::new("SHA256SUMS"), text.as_bytes())?,
+            None => {
+                fs::create_dir_all(&output_root)?;
+                write_atomic(&path, text.as_bytes())?;
+            }
+        }
+        files_written += 1;
+    }
+    if let Some(tar) = tarball {
+        tar.into_inner()
+            .and_then(|gz| gz.finish())
+            .and_then(|mut out| out.flush())
+            .and_then(|_| fs::rename(temp_path(&output), &output))
+            .with_context(|| format!("writing {}", output.display()))?;
+    }
+    if let Some(ev) = events {
+        ev.finish()?;
+    }
+    if !cli.quiet {
+        eprintln!("\nWrote {} files to {}", files_written, output.display());
+        eprintln!(
+            "Exported {} sessions, {} tokens, ${:.2}",
+            sessions_exported,
+            renderer::format_number_as(run_tokens, cli.number_format),
+            run_cost
+        );
+    }
+
+    if let Some(ref old) = cli.diff_against {
+        let current: HashSet<PathBuf> = written
+            .iter()
+            .map(|p| p.strip_prefix(&output_root).unwrap_or(p).to_path_buf())
+            .collect();
+        diff.removed = removed_files(old, &current, extension)?;
+        diff.new.sort();
+        diff.changed.sort();
+        for (label, paths) in [
+            ("new", &diff.new),
+            ("changed", &diff.changed),
+            ("removed", &diff.removed),
+        ] {
+            for path in paths {
+                println!("{:<8} {}", label, path.display());
+            }
+        }
+        println!(
+            "{} new, {} changed, {} removed, {} unchanged (vs {})",
+            diff.new.len(),
+            diff.changed.len(),
+            diff.removed.len(),
+            diff.unchanged,
+            old.display()
+        );
+    }
 
     Ok(())
 }
+
+/// Creation time (epoch ms) of the session named by a `--after-session`/`--before-session`
+/// cursor.
+fn cursor_created(data: &loader::StorageData, flag: &str, id: &str) -> Result<u64> {
+    let session = data
+        .sessions
+        .get(id)
+        .ok_or_else(|| anyhow!("{} {}: no such session", flag, id))?;
+    match session.time.created {
+        Some(created) => Ok(to_millis(created)),
+        None => bail!("{} {}: the session has no creation time", flag, id),
+    }
+}
+
+/// Whether `--output` names a `.tar.gz` to write instead of a directory.
+fn writes_archive(cli: &Cli) -> bool {
+    cli.archive
+        || cli.output.as_ref().is_some_and(|p| {
+            let name = p.to_string_lossy();
+            name.ends_with(".tar.gz") || name.ends_with(".tgz")
+        })
+}
+
+/// Hidden sibling of `path` that a file is written to before being renamed over `path`.
+fn temp_path(path: &Path) -> PathBuf {
+    let name = path
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    path.with_file_name(format!(".{}.tmp-{}", name, std::process::id()))
+}
+
+/// Write `data` to a temp file next to `path` and rename it into place, so an
+/// interrupted run leaves either the old file or the new one, never a partial one.
+fn write_atomic(path: &Path, data: &[u8]) -> Result<()> {
+    let tmp = temp_path(path);
+    fs::write(&tmp, data)
+        .and_then(|_| fs::rename(&tmp, path))
+        .inspect_err(|_| {
+            let _ = fs::remove_file(&tmp);
+        })
+        .with_context(|| format!("writing {}", path.display()))
+}
+
+/// Add a regular file entry to the `--output` tarball.
+fn append_file(tar: &mut TarGz, path: &Path, data: &[u8]) -> Result<()> {
+    let mut header = tar::Header::new_gnu();
+    header.set_size(data.len() as u64);
+    header.set_mode(0o644);
+    header.set_mtime(Utc::now().timestamp().max(0) as u64);
+    tar.append_data(&mut header, path, data)
+        .with_context(|| format!("adding {} to archive", path.display()))
+}
+
+/// Record `data`'s SHA-256 under `rel` when `--checksums` is on.
+fn add_checksum(sums: &mut Option<Vec<(PathBuf, String)>>, rel: &Path, data: &[u8]) {
+    if let Some(sums) = sums {
+        // A file overwritten later in the run (--on-collision overwrite) keeps its last hash
+        sums.retain(|(path, _)| path != rel);
+        sums.push((rel.to_path_buf(), format!("{:x}", Sha256::digest(data))));
+    }
+}
+
+/// File contents, or `None` if there is no such file.
+fn read_if_exists(path: &Path) -> Result<Option<Vec<u8>>> {
+    match fs::read(path) {
+        Ok(data) => Ok(Some(data)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e).with_context(|| format!("reading {}", path.display())),
+    }
+}
+
+/// `.<ext>` files under `old_root` whose relative path isn't in `current`, sorted.
+fn removed_files(old_root: &Path, current: &HashSet<PathBuf>, ext: &str) -> Result<Vec<PathBuf>> {
+    let mut removed = Vec::new();
+    if !old_root.is_dir() {
+        return Ok(removed);
+    }
+    let mut dirs = vec![old_root.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        for entry in fs::read_dir(&dir).with_context(|| format!("reading {}", dir.display()))? {
+            let path = entry?.path();
+            if path.is_dir() {
+                dirs.push(path);
+            } else if path.extension().is_some_and(|e| e == ext) {
+                let rel = path.strip_prefix(old_root).unwrap_or(&path).to_path_buf();
+                if !current.contains(&rel) {
+                    removed.push(rel);
+                }
+            }
+        }
+    }
+    removed.sort();
+    Ok(removed)
+}
+
+// ── Watch ───────────────────────────────────────────────────────────
+
+/// Re-export the sessions touched by each burst of storage changes, until interrupted.
+fn watch(cli: &Cli, storage_dir: &Path) -> Result<()> {
+    let root = storage_dir
+        .canonicalize()
+        .with_context(|| format!("resolving {}", storage_dir.display()))?;
+    let (tx, rx) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(tx).context("starting file watcher")?;
+    watcher
+        .watch(&root, RecursiveMode::Recursive)
+        .with_context(|| format!("watching {}", root.display()))?;
+    if !cli.quiet {
+        eprintln!(
+            "\nWatching {} for changes (Ctrl-C to stop) ...",
+            root.display()
+        );
+    }
+
+    loop {
+        let mut paths = Vec::new();
+        let first = rx.recv().context("file watcher stopped")?;
+        collect_paths(first, &mut paths);
+        // Wait for storage to stay quiet for 500 ms before re-exporting the batch
+        while let Ok(event) = rx.recv_timeout(StdDuration::from_millis(500)) {
+            collect_paths(event, &mut paths);
+        }
+
+        let changed: HashSet<String> = paths.iter().filter_map(|p| changed_id(&root, p)).collect();
+        if changed.is_empty() {
+            continue;
+        }
+        // One bad batch shouldn't end a long-running watch
+        if let Err(e) = export(cli, storage_dir, Some(&changed)) {
+            eprintln!("error: {:#}", e);
+        }
+    }
+}
+
+fn collect_paths(event: notify::Result<notify::Event>, paths: &mut Vec<PathBuf>) {
+    match event {
+        Ok(event) if !event.kind.is_access() => paths.extend(event.paths),
+        Ok(_) => {}
+        Err(e) => eprintln!("warn: file watcher: {}", e),
+    }
+}
+
+/// The session or message ID a changed storage path belongs to, if any.
+fn changed_id(root: &Path, path: &Path) -> Option<String> {
+    let rel = path.strip_prefix(root).ok()?;
+    let parts: Vec<&str> = rel.iter().map(|c| c.to_str()).collect::<Option<_>>()?;
+    match parts[..] {
+        ["session", _, file] | ["session_diff", file] | ["todo", file] => {
+            file.strip_suffix(".json").map(str::to_string)
+        }
+        // message/<session_id>/... and part/<message_id>/...
+        ["message", id, ..] | ["part", id, ..] => Some(id.to_string()),
+        _ => None,
+    }
+}
+
+/// Top-level sessions that the changed session and message IDs belong to.
+fn changed_roots<'a>(data: &'a loader::StorageData, changed: &HashSet<String>) -> HashSet<&'a str> {
+    let session_of: HashMap<&str, &str> = data
+        .messages_by_session
+        .values()
+        .flatten()
+        .map(|m| (m.id.as_str(), m.session_id.as_str()))
+        .collect();
+    changed
+        .iter()
+        .filter_map(|id| {
+            let session = session_of.get(id.as_str()).copied().unwrap_or(id);
+            resolver::root_session(data, session)
+        })
+        .collect()
+}
@@ -1,82 +1,679 @@
-use chrono::{TimeZone, Utc};
-use std::fmt::Write;
+use clap::ValueEnum;
+use similar::{ChangeTag, TextDiff};
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::{self, Write};
+use std::io;
 
 use crate::types::*;
 
+// ── Options ─────────────────────────────────────────────────────────
+
+/// Rendering switches, populated from CLI flags in `main.rs`.
+#[derive(Debug, Clone, Default)]
+pub struct RenderOptions {
+    pub empty_messages: EmptyMessages,
+    pub anonymize: Option<Anonymize>,
+    /// Message roles to render; empty means all
+    pub roles: Vec<String>,
+    /// Omit role headings and `---` separators between turns
+    pub bare_turns: bool,
+    /// Add a block-character bar chart under the Token Usage table
+    pub token_bars: bool,
+    /// Title untitled, slug-less sessions from their first user message
+    pub derive_titles: bool,
+    /// Render sub-agent turns at top level with a `(sub-agent: ...)` label instead of `> ` nesting
+    pub flatten_subagents: bool,
+    /// Render runs of assistant messages with the same model as one turn
+    pub merge_consecutive: bool,
+    /// Terms to bold in text parts (ASCII case-insensitive)
+    pub highlight: Vec<String>,
+    /// Render non-empty `metadata` on tool and reasoning parts as a JSON block
+    pub show_metadata: bool,
+    /// Truncate lines in tool code blocks to this many characters; 0 = no limit
+    pub clamp_width: usize,
+    /// Add a Tool Usage section with per-tool call counts
+    pub tool_stats: bool,
+    /// Dump every tool input as JSON instead of the per-tool views
+    pub raw_tool_input: bool,
+    /// Code fence characters for blocks the renderer emits
+    pub fence: FenceStyle,
+    /// URI scheme for an "open in opencode" header link; `None` leaves it out
+    pub deep_link: Option<String>,
+    /// How assistant models are named in headings and the header
+    pub model_label: ModelLabelStyle,
+    /// Wrap each message's tool calls, except `task`, in one `<details>` block
+    pub collapse_tools: bool,
+    /// How token counts are written
+    pub number_format: NumberFormat,
+    /// Whether Token Usage counts sub-agent messages
+    pub token_scope: TokenScope,
+    /// Add a `## Contents` list linking to each top-level turn
+    pub toc: bool,
+    /// Append each tool call's `callID` to its heading
+    pub show_call_ids: bool,
+    /// End the file with a one-line `<!-- oc-export: ... -->` token/cost summary
+    pub footer: bool,
+    /// Drop opaque signature blobs from reasoning text
+    pub strip_signatures: bool,
+    /// Collapse runs of 3+ blank lines outside code fences to 2
+    pub trim_empty_lines: bool,
+    /// Sections to render, in order; empty means `Section::default_order()`
+    pub section_order: Vec<Section>,
+    /// Order of the Task List
+    pub todo_sort: TodoSort,
+    /// Link read/write/edit file paths as `file://` URLs, resolving relative paths against
+    /// this worktree; `None` leaves them as plain code spans
+    pub link_files: Option<String>,
+    /// Levels to add to every heading the renderer writes (capped at `######`)
+    pub heading_offset: usize,
+    /// Number step annotations and add running token and cost totals to them
+    pub cumulative_steps: bool,
+    /// Show `write` calls as a diff against the file's earlier content when it is known
+    pub diff_writes: bool,
+    /// Bold a one-line summary of multi-line tool errors and collapse the full text
+    pub pretty_errors: bool,
+    /// Tag each turn heading with its offset from the session's creation (`[+2m30s]`)
+    pub relative_times: bool,
+    /// Explain the Task List markers and Files Changed counts in a line under each
+    pub legend: bool,
+}
+
+impl RenderOptions {
+    fn shows_role(&self, role: &str) -> bool {
+        self.roles.is_empty() || self.roles.iter().any(|r| r == role)
+    }
+
+    /// `section_order`, or the default order when it is empty.
+    fn sections(&self) -> Cow<'_, [Section]> {
+        if self.section_order.is_empty() {
+            Cow::Owned(Section::default_order())
+        } else {
+            Cow::Borrowed(&self.section_order)
+        }
+    }
+
+    /// `#` marks for a heading of `level`, shifted by `heading_offset`.
+    fn heading(&self, level: usize) -> String {
+        "#".repeat((level + self.heading_offset).min(6))
+    }
+
+    /// Model name for headings and the header, styled per `model_label`.
+    fn model_label<'a>(&self, message: &'a Message) -> Option<Cow<'a, str>> {
+        let model = message.effective_model()?;
+        Some(match self.model_label {
+            ModelLabelStyle::Model => Cow::Borrowed(model),
+            ModelLabelStyle::ProviderModel => match message.effective_provider() {
+                Some(provider) => Cow::Owned(format!("{}/{}", provider, model)),
+                None => Cow::Borrowed(model),
+            },
+            ModelLabelStyle::Short => Cow::Borrowed(strip_date_suffix(model)),
+        })
+    }
+
+    /// Fence to open and close a block holding `content`: at least three characters, and
+    /// one longer than the longest run of the same character inside, so content can't
+    /// close the block early.
+    fn fence(&self, content: &str) -> String {
+        let ch = match self.fence {
+            FenceStyle::Backtick => '`',
+            FenceStyle::Tilde => '~',
+            FenceStyle::Auto if content.contains("```") => '~',
+            FenceStyle::Auto => '`',
+        };
+        let longest = content
+            .split(|c| c != ch)
+            .map(|run| run.len())
+            .max()
+            .unwrap_or(0);
+        ch.to_string().repeat(longest.max(2) + 1)
+    }
+}
+
+/// Path scrubbing applied to the finished document with `--anonymize`.
+#[derive(Debug, Clone)]
+pub struct Anonymize {
+    /// Home directory prefix to replace with `~`
+    pub home: String,
+    /// Replacement for the project worktree path, if projects are relabelled
+    pub project_label: Option<String>,
+}
+
+impl Anonymize {
+    /// Scrub `project`'s worktree (when relabelled) and the home directory from `md`.
+    pub fn apply(&self, md: &str, project: &Project) -> String {
+        self.scrub(md, &project.worktree)
+    }
+
+    fn scrub(&self, text: &str, worktree: &str) -> String {
+        let mut out = text.to_string();
+        let worktree = worktree.trim_end_matches('/');
+        if let Some(ref label) = self.project_label {
+            if !worktree.is_empty() {
+                out = replace_path(&out, worktree, label);
+            }
+        }
+        let home = self.home.trim_end_matches(['/', '\\']);
+        // An empty or root home would match every absolute path
+        if home.len() > 1 {
+            out = replace_path(&out, home, "~");
+        }
+        out
+    }
+}
+
+/// Replace `from` with `to` where it is a whole path prefix: not followed by more of a
+/// file name, so a home of `/home/al` leaves `/home/alice` alone.
+fn replace_path(text: &str, from: &str, to: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(i) = rest.find(from) {
+        let after = &rest[i + from.len()..];
+        let whole = after
+            .chars()
+            .next()
+            .is_none_or(|c| !(c.is_alphanumeric() || c == '-' || c == '_'));
+        out.push_str(&rest[..i]);
+        out.push_str(if whole { to } else { from });
+        rest = after;
+    }
+    out.push_str(rest);
+    out
+}
+
+/// What to do with messages that have no renderable parts.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum EmptyMessages {
+    /// Render the role heading with a `> (no content)` note
+    #[default]
+    Placeholder,
+    /// Leave the message out entirely
+    Skip,
+}
+
+/// Which characters code fences use.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum FenceStyle {
+    /// Triple backticks
+    #[default]
+    Backtick,
+    /// Triple tildes
+    Tilde,
+    /// Tildes when the block contains triple backticks, backticks otherwise
+    Auto,
+}
+
+/// How `format_number_as` writes counts.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum NumberFormat {
+    /// `1.2M`, `3.4K`, `999`
+    #[default]
+    Abbrev,
+    /// `1,234,567`
+    Grouped,
+    /// `1234567`
+    Plain,
+}
+
+/// Which messages the Token Usage section counts.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum TokenScope {
+    /// The session's own messages
+    #[default]
+    Own,
+    /// The session's messages plus all of its sub-agents', at any depth
+    Total,
+}
+
+/// A top-level part of the Markdown document, for `--section-order`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, ValueEnum)]
+pub enum Section {
+    /// Title, metadata table and `--toc` contents
+    Header,
+    /// The turns, followed by any orphaned parts
+    Conversation,
+    /// Task List
+    Todos,
+    /// Files Changed
+    Diffs,
+    /// Tool Usage (only with `--tool-stats`)
+    Tools,
+    /// Token Usage
+    Tokens,
+}
+
+impl Section {
+    pub fn default_order() -> Vec<Section> {
+        vec![
+            Section::Header,
+            Section::Conversation,
+            Section::Todos,
+            Section::Diffs,
+            Section::Tools,
+            Section::Tokens,
+        ]
+    }
+}
+
+/// How the Task List orders todos.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum TodoSort {
+    /// As stored
+    #[default]
+    None,
+    /// By status (in progress, pending, completed, cancelled), then priority (high to low)
+    Status,
+    /// Sorted as `status`, under a heading per status; completed and cancelled ones collapsed
+    Grouped,
+}
+
+/// How model names are shown.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum ModelLabelStyle {
+    /// The model ID as stored, e.g. `claude-3-5-sonnet-20241022`
+    #[default]
+    Model,
+    /// Provider and model ID, e.g. `anthropic/claude-3-5-sonnet-20241022`
+    ProviderModel,
+    /// The model ID without a trailing release date, e.g. `claude-3-5-sonnet`
+    Short,
+}
+
+/// Drop a trailing `-YYYYMMDD` or `-YYYY-MM-DD` release date from a model ID.
+fn strip_date_suffix(model: &str) -> &str {
+    let digits = |s: &str, len: usize| s.len() == len && s.bytes().all(|b| b.is_ascii_digit());
+    let fields: Vec<&str> = model.rsplitn(4, '-').collect();
+    let date_len = match fields[..] {
+        [date, _, ..] if digits(date, 8) => 8,
+        [dd, mm, yyyy, _] if digits(dd, 2) && digits(mm, 2) && digits(yyyy, 4) => 10,
+        _ => return model,
+    };
+    &model[..model.len() - date_len - 1]
+}
+
 /// Render a resolved session to a formatted Markdown string.
-pub fn render_session(resolved: &ResolvedSession, project: &Project) -> String {
+pub fn render_session(
+    resolved: &ResolvedSession,
+    project: &Project,
+    opts: &RenderOptions,
+) -> String {
     let mut md = String::with_capacity(8192);
+    write_session(&mut md, resolved, project, opts);
+    if opts.trim_empty_lines {
+        md = trim_empty_lines(&md);
+    }
+    match opts.anonymize {
+        Some(ref anon) => anon.apply(&md, project),
+        None => md,
+    }
+}
+
+/// Render a session straight into `w` instead of building the document in memory, and
+/// return the number of bytes written.
+///
+/// `--anonymize` and `--trim-empty-lines` rework the finished text, so with either set the
+/// document is still rendered to a `String` first.
+pub fn render_session_to_writer<W: io::Write>(
+    w: &mut W,
+    resolved: &ResolvedSession,
+    project: &Project,
+    opts: &RenderOptions,
+) -> io::Result<u64> {
+    if opts.anonymize.is_some() || opts.trim_empty_lines {
+        let md = render_session(resolved, project, opts);
+        w.write_all(md.as_bytes())?;
+        return Ok(md.len() as u64);
+    }
+    let mut out = IoWriter {
+        inner: w,
+        bytes: 0,
+        error: None,
+    };
+    write_session(&mut out, resolved, project, opts);
+    match out.error {
+        Some(e) => Err(e),
+        None => Ok(out.bytes),
+    }
+}
 
-    // ── Header ──────────────────────────────────────────────────────
+/// Lets the `fmt::Write`-based renderer write to an `io::Write`. The first IO error is
+/// kept and later output dropped, so the `writeln!(...).unwrap()` calls never panic.
+struct IoWriter<'a, W: io::Write> {
+    inner: &'a mut W,
+    bytes: u64,
+    error: Option<io::Error>,
+}
+
+impl<W: io::Write> Write for IoWriter<'_, W> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        if self.error.is_none() {
+            match self.inner.write_all(s.as_bytes()) {
+                Ok(()) => self.bytes += s.len() as u64,
+                Err(e) => self.error = Some(e),
+            }
+        }
+        Ok(())
+    }
+}
+
+fn write_session<W: Write>(
+    md: &mut W,
+    resolved: &ResolvedSession,
+    project: &Project,
+    opts: &RenderOptions,
+) {
+    for section in opts.sections().iter() {
+        match section {
+            Section::Header => write_header(md, resolved, project, opts),
+            Section::Conversation => write_conversation(md, resolved, opts),
+            Section::Todos => write_todos(md, resolved, opts),
+            Section::Diffs => write_diffs(md, resolved, opts),
+            Section::Tools => write_tool_usage(md, resolved, opts),
+            Section::Tokens => write_token_usage(md, resolved, opts),
+        }
+    }
+    if opts.footer {
+        write_footer(md, resolved, opts);
+    }
+}
+
+/// Token totals and cost for the Token Usage section and footer, per `token_scope`.
+fn scoped_totals(resolved: &ResolvedSession, opts: &RenderOptions) -> (Tokens, f64) {
+    match opts.token_scope {
+        TokenScope::Own => (resolved.token_totals.clone(), resolved.cost_total),
+        TokenScope::Total => resolved.totals_with_sub_agents(),
+    }
+}
+
+// ── Header ──────────────────────────────────────────────────────────
+
+fn write_header<W: Write>(
+    md: &mut W,
+    resolved: &ResolvedSession,
+    project: &Project,
+    opts: &RenderOptions,
+) {
+    let derived = opts
+        .derive_titles
+        .then(|| resolved.derived_title())
+        .flatten();
     let title = resolved
         .session
         .title
         .as_deref()
+        .or(derived.as_deref())
         .unwrap_or("Untitled Session");
 
     let date = format_timestamp(resolved.session.time.created);
     let version = resolved.session.version.as_deref().unwrap_or("unknown");
 
-    // Detect primary model from first assistant message
-    let primary_model = resolved
-        .messages
-        .iter()
-        .filter_map(|item| match item {
-            ResolvedConversationItem::Message(rm) => {
-                if rm.message.role == "assistant" {
-                    rm.message.effective_model().map(|s| s.to_string())
-                } else {
-                    None
+    // Distinct assistant models in order of first use; the first is the primary one.
+    // Sub-agents are excluded: their models show in their own headings.
+    let mut models: Vec<Cow<str>> = Vec::new();
+    for item in &resolved.messages {
+        if let ResolvedConversationItem::Message(rm) = item {
+            if rm.message.role == "assistant" {
+                if let Some(model) = opts.model_label(&rm.message) {
+                    if !models.contains(&model) {
+                        models.push(model);
+                    }
                 }
             }
-            _ => None,
-        })
-        .next()
-        .unwrap_or_else(|| "unknown".to_string());
+        }
+    }
+    let primary_model = models.first().map_or("unknown", |m| m.as_ref());
 
-    writeln!(md, "# {}\n", title).unwrap();
+    writeln!(md, "{} {}\n", opts.heading(1), title).unwrap();
     writeln!(md, "| | |").unwrap();
     writeln!(md, "|---|---|").unwrap();
     writeln!(md, "| **Project** | `{}` |", project.worktree).unwrap();
+    if let Some(ref vcs) = project.vcs {
+        writeln!(md, "| **VCS** | {} |", vcs).unwrap();
+    }
     writeln!(md, "| **Date** | {} |", date).unwrap();
+    // Only when it was touched after the creation minute, i.e. edited or resumed
+    if resolved.session.time.updated.is_some() {
+        let updated = format_timestamp(resolved.session.time.updated);
+        if updated != date {
+            writeln!(md, "| **Updated** | {} |", updated).unwrap();
+        }
+    }
     writeln!(md, "| **Model** | {} |", primary_model).unwrap();
+    if models.len() > 1 {
+        writeln!(md, "| **Models** | {} |", models.join(", ")).unwrap();
+    }
     writeln!(md, "| **Version** | opencode {} |", version).unwrap();
+    writeln!(
+        md,
+        "| **Reading time** | {} |",
+        reading_time(word_count(&resolved.messages))
+    )
+    .unwrap();
     if let Some(ref slug) = resolved.session.slug {
         writeln!(md, "| **Slug** | {} |", slug).unwrap();
     }
     writeln!(md, "| **Session** | `{}` |", resolved.session.id).unwrap();
+    if !resolved.ancestors.is_empty() {
+        let crumbs: Vec<String> = resolved
+            .ancestors
+            .iter()
+            .map(|s| {
+                let name = s.title.as_deref().or(s.slug.as_deref()).unwrap_or(&s.id);
+                match opts.deep_link {
+                    Some(ref scheme) => format!("[{}]({}://session/{})", name, scheme, s.id),
+                    None => format!("{} (`{}`)", name, s.id),
+                }
+            })
+            .collect();
+        writeln!(md, "| **Parent** | {} |", crumbs.join(" → ")).unwrap();
+    }
+    if let Some(ref scheme) = opts.deep_link {
+        writeln!(
+            md,
+            "| **Open** | [open in opencode]({}://session/{}) |",
+            scheme, resolved.session.id
+        )
+        .unwrap();
+    }
+    let in_progress = resolved.in_progress();
+    if in_progress {
+        writeln!(md, "| **Status** | in progress |").unwrap();
+    }
     writeln!(md).unwrap();
+    if in_progress {
+        writeln!(md, "> ⏳ This session may still be active\n").unwrap();
+    }
     writeln!(md, "---\n").unwrap();
 
-    // ── Conversation ────────────────────────────────────────────────
-    render_conversation_items(&mut md, &resolved.messages, 0);
+    // The contents list links into the conversation, so it needs one below
+    if opts.toc && !opts.bare_turns && opts.sections().contains(&Section::Conversation) {
+        render_toc(md, &resolved.messages, opts);
+    }
+}
 
-    // ── Todos ───────────────────────────────────────────────────────
-    if !resolved.todos.is_empty() {
-        writeln!(md, "---\n").unwrap();
-        writeln!(md, "## Task List\n").unwrap();
-        for todo in &resolved.todos {
-            let check = match todo.status.as_str() {
-                "completed" => "[x]",
-                "in_progress" => "[-]",
-                "cancelled" => "[~]",
-                _ => "[ ]",
-            };
-            let priority_badge = match todo.priority.as_deref() {
-                Some("high") => " `HIGH`",
-                Some("medium") => " `MED`",
-                Some("low") => " `LOW`",
-                _ => "",
-            };
-            writeln!(md, "- {} {}{}", check, todo.content, priority_badge).unwrap();
+// ── Conversation ────────────────────────────────────────────────────
+
+fn write_conversation<W: Write>(md: &mut W, resolved: &ResolvedSession, opts: &RenderOptions) {
+    if resolved.omitted_before > 0 {
+        writeln!(
+            md,
+            "> ... ({} earlier messages omitted) ...\n",
+            resolved.omitted_before
+        )
+        .unwrap();
+    }
+    let mut doc = DocState {
+        // Not the first message shown, which --reverse-messages and --message-since move
+        start: resolved.session.time.created.map(to_millis),
+        // Session diffs hold each file's state before the session, which is only the
+        // state before its first write when the conversation isn't cut at the start
+        diff_befores: if opts.diff_writes && resolved.omitted_before == 0 {
+            resolved
+                .diffs
+                .iter()
+                .filter_map(|d| Some((d.file.clone(), d.before.clone()?)))
+                .collect()
+        } else {
+            Vec::new()
+        },
+        ..DocState::default()
+    };
+    render_conversation_items(md, &resolved.messages, None, &mut doc, opts);
+    if resolved.omitted_items > 0 {
+        writeln!(
+            md,
+            "> ... ({} later messages omitted) ...\n",
+            resolved.omitted_items
+        )
+        .unwrap();
+    }
+
+    // Orphaned parts
+    if !resolved.orphan_parts.is_empty() {
+        writeln!(md, "{} Orphaned Parts\n", opts.heading(2)).unwrap();
+        writeln!(md, "*Parts whose message is missing from storage.*\n").unwrap();
+        let mut current: Option<&str> = None;
+        let mut doc = DocState::default();
+        for part in &resolved.orphan_parts {
+            if current != Some(part.message_id.as_str()) {
+                writeln!(md, "{} Message `{}`\n", opts.heading(3), part.message_id).unwrap();
+                current = Some(&part.message_id);
+            }
+            render_part(md, part, "", &mut doc, opts);
+        }
+    }
+}
+
+// ── Todos ───────────────────────────────────────────────────────────
+
+fn write_todos<W: Write>(md: &mut W, resolved: &ResolvedSession, opts: &RenderOptions) {
+    if resolved.todos.is_empty() {
+        return;
+    }
+    writeln!(md, "---\n").unwrap();
+    writeln!(md, "{} Task List\n", opts.heading(2)).unwrap();
+    let mut todos: Vec<&TodoEntry> = resolved.todos.iter().collect();
+    if opts.todo_sort != TodoSort::None {
+        // Stable, so ties keep their stored order
+        todos.sort_by_key(|t| (status_rank(&t.status), priority_rank(t.priority.as_deref())));
+    }
+    if opts.todo_sort != TodoSort::Grouped {
+        for todo in todos {
+            write_todo(md, todo);
         }
         writeln!(md).unwrap();
+    } else {
+        write_todo_groups(md, &todos, opts);
+    }
+    if opts.legend {
+        write_todo_legend(md, &resolved.todos);
+    }
+}
+
+/// The `--todo-sort grouped` Task List: a heading per status, done ones collapsed.
+fn write_todo_groups<W: Write>(md: &mut W, todos: &[&TodoEntry], opts: &RenderOptions) {
+    for group in todos.chunk_by(|a, b| status_rank(&a.status) == status_rank(&b.status)) {
+        let status = group[0].status.as_str();
+        let heading = match status_rank(status) {
+            0 => "In Progress",
+            1 => "Pending",
+            2 => "Completed",
+            3 => "Cancelled",
+            _ => "Other",
+        };
+        let collapsed = matches!(status, "completed" | "cancelled");
+        if collapsed {
+            writeln!(
+                md,
+                "<details>\n<summary>{} ({})</summary>\n",
+                heading,
+                group.len()
+            )
+            .unwrap();
+        } else {
+            writeln!(md, "{} {}\n", opts.heading(3), heading).unwrap();
+        }
+        for todo in group {
+            write_todo(md, todo);
+        }
+        writeln!(md).unwrap();
+        if collapsed {
+            writeln!(md, "</details>\n").unwrap();
+        }
+    }
+}
+
+/// `--legend` key for the markers that appear in `todos`, in Task List order.
+fn write_todo_legend<W: Write>(md: &mut W, todos: &[TodoEntry]) {
+    let mut keys = Vec::new();
+    for (status, key) in [
+        ("in_progress", "`[-]` in progress"),
+        ("pending", "`[ ]` pending"),
+        ("completed", "`[x]` completed"),
+        ("cancelled", "`[~]` cancelled"),
+    ] {
+        if todos.iter().any(|t| t.status == status) {
+            keys.push(key);
+        }
+    }
+    // Unknown statuses render as pending boxes too
+    if !keys.contains(&"`[ ]` pending") && todos.iter().any(|t| status_rank(&t.status) == 4) {
+        keys.push("`[ ]` pending");
+    }
+    let badges: Vec<&str> = [("high", "`HIGH`"), ("medium", "`MED`"), ("low", "`LOW`")]
+        .into_iter()
+        .filter(|(p, _)| todos.iter().any(|t| t.priority.as_deref() == Some(*p)))
+        .map(|(_, badge)| badge)
+        .collect();
+    let priority = format!("{} priority", badges.join(" / "));
+    if !badges.is_empty() {
+        keys.push(&priority);
     }
+    writeln!(md, "*Key: {}*\n", keys.join(" · ")).unwrap();
+}
+
+fn write_todo<W: Write>(md: &mut W, todo: &TodoEntry) {
+    let check = match todo.status.as_str() {
+        "completed" => "[x]",
+        "in_progress" => "[-]",
+        "cancelled" => "[~]",
+        _ => "[ ]",
+    };
+    let priority_badge = match todo.priority.as_deref() {
+        Some("high") => " `HIGH`",
+        Some("medium") => " `MED`",
+        Some("low") => " `LOW`",
+        _ => "",
+    };
+    writeln!(md, "- {} {}{}", check, todo.content, priority_badge).unwrap();
+}
+
+/// Sort key for `--todo-sort`: active work first, unknown statuses last.
+fn status_rank(status: &str) -> u8 {
+    match status {
+        "in_progress" => 0,
+        "pending" => 1,
+        "completed" => 2,
+        "cancelled" => 3,
+        _ => 4,
+    }
+}
 
-    // ── File Changes ────────────────────────────────────────────────
+fn priority_rank(priority: Option<&str>) -> u8 {
+    match priority {
+        Some("high") => 0,
+        Some("medium") => 1,
+        Some("low") => 2,
+        _ => 3,
+    }
+}
+
+// ── File Changes ────────────────────────────────────────────────────
+
+fn write_diffs<W: Write>(md: &mut W, resolved: &ResolvedSession, opts: &RenderOptions) {
     if !resolved.diffs.is_empty() {
         writeln!(md, "---\n").unwrap();
-        writeln!(md, "## Files Changed\n").unwrap();
+        writeln!(md, "{} Files Changed\n", opts.heading(2)).unwrap();
         for diff in &resolved.diffs {
             let status = diff.status.as_deref().unwrap_or("modified");
             let adds = diff.additions.unwrap_or(0);
@@ -84,10 +681,41 @@ pub fn render_session(resolved: &ResolvedSession, project: &Project) -> String {
             writeln!(md, "- **{}** ({}) +{} / -{}", diff.file, status, adds, dels).unwrap();
         }
         writeln!(md).unwrap();
+        if opts.legend {
+            writeln!(md, "*Key: +lines added / -lines removed*\n").unwrap();
+        }
     }
+}
+
+// ── Tool Usage ──────────────────────────────────────────────────────
+
+fn write_tool_usage<W: Write>(md: &mut W, resolved: &ResolvedSession, opts: &RenderOptions) {
+    if opts.tool_stats {
+        let mut tally = BTreeMap::new();
+        tool_tally(&resolved.messages, false, &mut tally);
+        if !tally.is_empty() {
+            let mut rows: Vec<(&str, usize, usize)> = tally
+                .iter()
+                .map(|(tool, &(own, sub))| (tool.as_str(), own, sub))
+                .collect();
+            // Stable sort keeps ties in name order
+            rows.sort_by_key(|&(_, own, sub)| std::cmp::Reverse(own + sub));
+            writeln!(md, "---\n").unwrap();
+            writeln!(md, "{} Tool Usage\n", opts.heading(2)).unwrap();
+            writeln!(md, "| Tool | Calls | Sub-agent calls |").unwrap();
+            writeln!(md, "|---|---:|---:|").unwrap();
+            for (tool, own, sub) in rows {
+                writeln!(md, "| `{}` | {} | {} |", tool, own, sub).unwrap();
+            }
+            writeln!(md).unwrap();
+        }
+    }
+}
+
+// ── Token Summary ───────────────────────────────────────────────────
 
-    // ── Token Summary ───────────────────────────────────────────────
-    let t = &resolved.token_totals;
+fn write_token_usage<W: Write>(md: &mut W, resolved: &ResolvedSession, opts: &RenderOptions) {
+    let (t, _) = scoped_totals(resolved, opts);
     let total_in = t.input.unwrap_or(0);
     let total_out = t.output.unwrap_or(0);
     let total_reason = t.reasoning.unwrap_or(0);
@@ -96,16 +724,45 @@ pub fn render_session(resolved: &ResolvedSession, project: &Project) -> String {
 
     if total_in + total_out > 0 {
         writeln!(md, "---\n").unwrap();
-        writeln!(md, "## Token Usage\n").unwrap();
+        let scope = match opts.token_scope {
+            TokenScope::Own => "",
+            TokenScope::Total => " (including sub-agents)",
+        };
+        writeln!(md, "{} Token Usage{}\n", opts.heading(2), scope).unwrap();
         writeln!(md, "| Metric | Count |").unwrap();
         writeln!(md, "|---|---:|").unwrap();
-        writeln!(md, "| Input | {} |", format_number(total_in)).unwrap();
-        writeln!(md, "| Output | {} |", format_number(total_out)).unwrap();
+        writeln!(
+            md,
+            "| Input | {} |",
+            format_number_as(total_in, opts.number_format)
+        )
+        .unwrap();
+        writeln!(
+            md,
+            "| Output | {} |",
+            format_number_as(total_out, opts.number_format)
+        )
+        .unwrap();
         if total_reason > 0 {
-            writeln!(md, "| Reasoning | {} |", format_number(total_reason)).unwrap();
+            writeln!(
+                md,
+                "| Reasoning | {} |",
+                format_number_as(total_reason, opts.number_format)
+            )
+            .unwrap();
         }
-        writeln!(md, "| Cache Read | {} |", format_number(cache_r)).unwrap();
-        writeln!(md, "| Cache Write | {} |", format_number(cache_w)).unwrap();
+        writeln!(
+            md,
+            "| Cache Read | {} |",
+            format_number_as(cache_r, opts.number_format)
+        )
+        .unwrap();
+        writeln!(
+            md,
+            "| Cache Write | {} |",
+            format_number_as(cache_w, opts.number_format)
+        )
+        .unwrap();
         let summary_adds = resolved.session.summary.additions.unwrap_or(0);
         let summary_dels = resolved.session.summary.deletions.unwrap_or(0);
         let summary_files = resolved.session.summary.files.unwrap_or(0);
@@ -118,55 +775,468 @@ pub fn render_session(resolved: &ResolvedSession, project: &Project) -> String {
             .unwrap();
         }
         writeln!(md).unwrap();
+
+        if opts.token_bars {
+            let total = t.total();
+            let fence = opts.fence("");
+            writeln!(md, "{}text", fence).unwrap();
+            for (label, n) in [
+                ("input", total_in),
+                ("output", total_out),
+                ("reasoning", total_reason),
+            ] {
+                if label == "reasoning" && n == 0 {
+                    continue;
+                }
+                writeln!(md, "{:<9} {}", label, token_bar(n, total)).unwrap();
+            }
+            writeln!(md, "{}\n", fence).unwrap();
+        }
     }
+}
+
+// ── Footer ──────────────────────────────────────────────────────────
 
-    md
+/// Written last whatever `--section-order` says.
+fn write_footer<W: Write>(md: &mut W, resolved: &ResolvedSession, opts: &RenderOptions) {
+    let (t, cost) = scoped_totals(resolved, opts);
+    let files = resolved
+        .session
+        .summary
+        .files
+        .unwrap_or(resolved.diffs.len() as u64);
+    writeln!(
+        md,
+        "<!-- oc-export: {} in / {} out / ${:.2} / {} files -->",
+        format_number_as(t.input.unwrap_or(0), opts.number_format),
+        format_number_as(t.output.unwrap_or(0), opts.number_format),
+        cost,
+        files
+    )
+    .unwrap();
 }
 
 // ── Conversation rendering ──────────────────────────────────────────
 
-fn render_conversation_items(md: &mut String, items: &[ResolvedConversationItem], depth: usize) {
-    for item in items {
-        match item {
+/// `context` names the enclosing sub-agent; `None` at the top level of the session.
+fn render_conversation_items<W: Write>(
+    md: &mut W,
+    items: &[ResolvedConversationItem],
+    context: Option<&str>,
+    doc: &mut DocState,
+    opts: &RenderOptions,
+) {
+    let mut i = 0;
+    while i < items.len() {
+        match &items[i] {
             ResolvedConversationItem::Message(rm) => {
-                render_message(md, rm, depth);
+                let run = if opts.merge_consecutive {
+                    assistant_run_len(&items[i..])
+                } else {
+                    1
+                };
+                if run > 1 {
+                    let mut merged = rm.clone();
+                    for item in &items[i + 1..i + run] {
+                        if let ResolvedConversationItem::Message(next) = item {
+                            merged.parts.extend(next.parts.iter().cloned());
+                        }
+                    }
+                    render_message(md, &merged, context, doc, opts);
+                } else {
+                    render_message(md, rm, context, doc, opts);
+                }
+                i += run;
             }
             ResolvedConversationItem::SubAgent { session, messages } => {
-                render_sub_agent(md, session, messages, depth);
+                render_sub_agent(md, session, messages, doc, opts);
+                i += 1;
             }
         }
     }
 }
 
-fn render_message(md: &mut String, rm: &ResolvedMessage, depth: usize) {
-    let prefix = if depth > 0 { "> " } else { "" };
+/// `## Contents` linking to each top-level turn heading by the anchor GitHub gives it
+/// (`#user-1`, `#assistant-2`, ...). Mirrors `render_conversation_items` so only turns
+/// that get a heading are listed, numbered the same way as `render_message` does.
+fn render_toc<W: Write>(md: &mut W, items: &[ResolvedConversationItem], opts: &RenderOptions) {
+    let mut entries = Vec::new();
+    let mut i = 0;
+    while i < items.len() {
+        let ResolvedConversationItem::Message(rm) = &items[i] else {
+            i += 1;
+            continue;
+        };
+        let run = if opts.merge_consecutive {
+            assistant_run_len(&items[i..])
+        } else {
+            1
+        };
+        let has_content = items[i..i + run].iter().any(|item| {
+            matches!(item, ResolvedConversationItem::Message(m)
+                if m.parts.iter().any(part_has_content))
+        });
+        i += run;
+
+        let role = rm.message.role.as_str();
+        if !opts.shows_role(role) || (!has_content && opts.empty_messages == EmptyMessages::Skip) {
+            continue;
+        }
+        let n = entries.len() + 1;
+        let entry = match role {
+            "user" => {
+                let first_line = rm.parts.iter().find_map(|p| match &p.kind {
+                    PartKind::Text { text, .. } => text.lines().find(|l| !l.trim().is_empty()),
+                    _ => None,
+                });
+                match first_line {
+                    Some(line) => {
+                        let line = line.trim();
+                        let snippet: String = line.chars().take(60).collect();
+                        let ellipsis = if snippet.len() < line.len() {
+                            "…"
+                        } else {
+                            ""
+                        };
+                        format!("[User](#user-{}): {}{}", n, snippet.trim_end(), ellipsis)
+                    }
+                    None => format!("[User](#user-{})", n),
+                }
+            }
+            "assistant" => {
+                let model = opts
+                    .model_label(&rm.message)
+                    .unwrap_or(Cow::Borrowed("assistant"));
+                format!("[Assistant ({})](#assistant-{})", model, n)
+            }
+            _ => continue,
+        };
+        entries.push(entry);
+    }
+    if entries.is_empty() {
+        return;
+    }
+    writeln!(md, "{} Contents\n", opts.heading(2)).unwrap();
+    for (n, entry) in entries.iter().enumerate() {
+        writeln!(md, "{}. {}", n + 1, entry).unwrap();
+    }
+    writeln!(md, "\n---\n").unwrap();
+}
+
+/// How many assistant messages with the first one's model start `items` (at least 1).
+/// User messages, sub-agents, and model switches end the run.
+fn assistant_run_len(items: &[ResolvedConversationItem]) -> usize {
+    let first = match items.first() {
+        Some(ResolvedConversationItem::Message(rm)) if rm.message.role == "assistant" => rm,
+        _ => return 1,
+    };
+    let model = first.message.effective_model();
+    1 + items[1..]
+        .iter()
+        .take_while(|item| {
+            matches!(item, ResolvedConversationItem::Message(rm)
+                if rm.message.role == "assistant" && rm.message.effective_model() == model)
+        })
+        .count()
+}
+
+fn render_message<W: Write>(
+    md: &mut W,
+    rm: &ResolvedMessage,
+    context: Option<&str>,
+    doc: &mut DocState,
+    opts: &RenderOptions,
+) {
+    let prefix = if context.is_some() && !opts.flatten_subagents {
+        "> "
+    } else {
+        ""
+    };
+    let label = match context {
+        Some(name) if opts.flatten_subagents => format!(" (sub-agent: {})", name),
+        // A nested `User` heading would otherwise take GitHub's `user-1`, `user-2`, ...
+        // anchors for its duplicates, which are the ones `--toc` links to
+        Some(_) if opts.toc => " (sub-agent)".to_string(),
+        _ => String::new(),
+    };
     let role = &rm.message.role;
+    let offset = match (doc.start, rm.message.time.created) {
+        (Some(start), Some(created)) if opts.relative_times => format!(
+            "[+{}] ",
+            format_offset(to_millis(created).saturating_sub(start))
+        ),
+        _ => String::new(),
+    };
+
+    if !opts.shows_role(role) {
+        return;
+    }
+
+    let has_content = rm.parts.iter().any(part_has_content);
+    if !has_content && (opts.empty_messages == EmptyMessages::Skip || opts.bare_turns) {
+        return;
+    }
+
+    if opts.bare_turns {
+        render_parts(md, &rm.parts, prefix, doc, opts);
+        return;
+    }
 
-    if role == "user" {
-        writeln!(md, "{}## User\n", prefix).unwrap();
+    let model = opts
+        .model_label(&rm.message)
+        .unwrap_or(Cow::Borrowed("assistant"));
+    let mode = rm.message.mode.as_deref().unwrap_or("");
+    let mode_badge = if !mode.is_empty() && mode != "code" {
+        format!(" `{}`", mode)
+    } else {
+        String::new()
+    };
+
+    if opts.toc && context.is_none() && (role == "user" || role == "assistant") {
+        // Only role and number, so GitHub's anchor is the `#user-<n>` / `#assistant-<n>`
+        // that `--toc` links to; the offset and model go on the line below
+        doc.turns += 1;
+        let name = if role == "user" { "User" } else { "Assistant" };
+        writeln!(md, "{} {} {}\n", opts.heading(2), name, doc.turns).unwrap();
+        if role == "assistant" {
+            writeln!(md, "{}*{}*{}\n", offset, model, mode_badge).unwrap();
+        } else if !offset.is_empty() {
+            writeln!(md, "{}\n", offset.trim_end()).unwrap();
+        }
+    } else if role == "user" {
+        writeln!(
+            md,
+            "{}{} {}User{}\n",
+            prefix,
+            opts.heading(2),
+            offset,
+            label
+        )
+        .unwrap();
     } else if role == "assistant" {
-        let model = rm.message.effective_model().unwrap_or("assistant");
-        let mode = rm.message.mode.as_deref().unwrap_or("");
-        let mode_badge = if !mode.is_empty() && mode != "code" {
-            format!(" `{}`", mode)
-        } else {
-            String::new()
-        };
-        writeln!(md, "{}## Assistant ({}){}\n", prefix, model, mode_badge).unwrap();
+        writeln!(
+            md,
+            "{}{} {}Assistant ({}){}{}\n",
+            prefix,
+            opts.heading(2),
+            offset,
+            model,
+            mode_badge,
+            label
+        )
+        .unwrap();
     }
 
     // Render parts
-    for part in &rm.parts {
-        render_part(md, part, prefix);
+    if has_content {
+        render_parts(md, &rm.parts, prefix, doc, opts);
+    } else {
+        writeln!(md, "{}> (no content)\n", prefix).unwrap();
     }
 
     writeln!(md, "{}---\n", prefix).unwrap();
 }
 
-fn render_part(md: &mut String, part: &Part, prefix: &str) {
+/// Render a message's parts in order. With `--collapse-tools`, its tool calls (except
+/// `task`, whose sub-agent follows inline) go in one `<details>` block where the first
+/// of them would be.
+fn render_parts<W: Write>(
+    md: &mut W,
+    parts: &[Part],
+    prefix: &str,
+    doc: &mut DocState,
+    opts: &RenderOptions,
+) {
+    let collapsible = |part: &Part| {
+        opts.collapse_tools && matches!(&part.kind, PartKind::Tool { tool, .. } if tool != "task")
+    };
+    let tools: Vec<&Part> = parts.iter().filter(|p| collapsible(p)).collect();
+    let mut collapsed = false;
+    for part in parts {
+        if !collapsible(part) {
+            render_part(md, part, prefix, doc, opts);
+        } else if !collapsed {
+            let noun = if tools.len() == 1 { "call" } else { "calls" };
+            writeln!(md, "{}<details>", prefix).unwrap();
+            writeln!(
+                md,
+                "{}<summary>{} tool {}</summary>\n",
+                prefix,
+                tools.len(),
+                noun
+            )
+            .unwrap();
+            for tool in &tools {
+                render_part(md, tool, prefix, doc, opts);
+            }
+            writeln!(md, "{}</details>\n", prefix).unwrap();
+            collapsed = true;
+        }
+    }
+}
+
+/// Count tool calls per tool name as `(session's own, inside sub-agents)`.
+fn tool_tally(
+    items: &[ResolvedConversationItem],
+    in_sub_agent: bool,
+    tally: &mut BTreeMap<String, (usize, usize)>,
+) {
+    for item in items {
+        match item {
+            ResolvedConversationItem::Message(rm) => {
+                for part in &rm.parts {
+                    if let PartKind::Tool { tool, .. } = &part.kind {
+                        let entry = tally.entry(tool.clone()).or_default();
+                        if in_sub_agent {
+                            entry.1 += 1;
+                        } else {
+                            entry.0 += 1;
+                        }
+                    }
+                }
+            }
+            ResolvedConversationItem::SubAgent { messages, .. } => {
+                tool_tally(messages, true, tally);
+            }
+        }
+    }
+}
+
+/// Whether `render_part` would emit anything for this part.
+fn part_has_content(part: &Part) -> bool {
+    match &part.kind {
+        PartKind::Text { text, .. } => !text.is_empty(),
+        PartKind::Tool { .. } => true,
+        PartKind::StepFinish { tokens, .. } => tokens
+            .as_ref()
+            .is_some_and(|t| t.output.unwrap_or(0) + t.reasoning.unwrap_or(0) > 0),
+        PartKind::Reasoning { text, .. } => text.as_ref().is_some_and(|t| !t.is_empty()),
+        PartKind::Patch { files, .. } => files.as_ref().is_some_and(|f| !f.is_empty()),
+        PartKind::Permission { .. } => true,
+        PartKind::StepStart { .. } | PartKind::Unknown => false,
+    }
+}
+
+/// What a document's conversation has shown so far, in rendered order: running step
+/// totals for `--cumulative-steps`, the time `--relative-times` offsets count from,
+/// file contents for `--diff-writes`, and the turns numbered for `--toc`.
+#[derive(Debug, Default)]
+struct DocState {
+    steps: usize,
+    /// Top-level User/Assistant headings written so far
+    turns: usize,
+    output: u64,
+    reasoning: u64,
+    cost: f64,
+    /// The session's `time.created` (epoch ms)
+    start: Option<u64>,
+    /// Content of each file path as last read, written, or edited; `None` once an edit
+    /// couldn't be replayed and the content is unknown
+    files: HashMap<String, Option<String>>,
+    /// `(file, before)` from the session's diff entries, for files not yet touched
+    diff_befores: Vec<(String, String)>,
+}
+
+impl DocState {
+    /// Content of `path` before the tool call being rendered, if known.
+    fn prior_content(&self, path: &str) -> Option<&str> {
+        match self.files.get(path) {
+            Some(known) => known.as_deref(),
+            // Diff entries use project-relative paths; tool inputs are absolute
+            None => self
+                .diff_befores
+                .iter()
+                .find(|(file, _)| path == file || path.ends_with(&format!("/{}", file)))
+                .map(|(_, before)| before.as_str()),
+        }
+    }
+
+    /// Record what a `read`, `write`, or `edit` call left in its file.
+    fn track_file(&mut self, tool: &str, state: &ToolState) {
+        let Some(input) = state.input.as_ref().filter(|_| state.error.is_none()) else {
+            return;
+        };
+        let Some(path) = input.get("filePath").and_then(|v| v.as_str()) else {
+            return;
+        };
+        let str_field = |key: &str| input.get(key).and_then(|v| v.as_str());
+        let content = match tool {
+            "write" => str_field("content").map(str::to_string),
+            "edit" => {
+                let prior = self.prior_content(path);
+                match (prior, str_field("oldString"), str_field("newString")) {
+                    (Some(prior), Some(old), Some(new))
+                        if !old.is_empty() && prior.contains(old) =>
+                    {
+                        if input.get("replaceAll").and_then(|v| v.as_bool()) == Some(true) {
+                            Some(prior.replace(old, new))
+                        } else {
+                            Some(prior.replacen(old, new, 1))
+                        }
+                    }
+                    _ => None,
+                }
+            }
+            // Only a whole-file read says what the file holds
+            "read" if input.get("offset").is_none() && input.get("limit").is_none() => {
+                match state.output.as_deref().and_then(read_content) {
+                    Some(content) => Some(content),
+                    None => return,
+                }
+            }
+            _ => return,
+        };
+        self.files.insert(path.to_string(), content);
+    }
+}
+
+/// File content from a `read` tool's output: the `<file>` wrapper, `00001| ` line
+/// numbers, and end-of-file note opencode adds are removed. `None` if the read was cut
+/// short.
+fn read_content(output: &str) -> Option<String> {
+    let body = match output.split_once("<file>\n") {
+        Some((_, rest)) => rest.rsplit_once("</file>").map_or(rest, |(body, _)| body),
+        None => output,
+    };
+    if body.contains("(File has more lines") {
+        return None;
+    }
+    let mut lines: Vec<&str> = body
+        .lines()
+        .filter(|line| !line.starts_with("(End of file"))
+        .collect();
+    while lines.last().is_some_and(|line| line.is_empty()) {
+        lines.pop();
+    }
+    if lines.is_empty() || !lines.iter().all(|line| strip_line_number(line).is_some()) {
+        return Some(body.to_string());
+    }
+    let mut content: String = lines
+        .iter()
+        .filter_map(|line| strip_line_number(line))
+        .collect::<Vec<_>>()
+        .join("\n");
+    content.push('\n');
+    Some(content)
+}
+
+fn render_part<W: Write>(
+    md: &mut W,
+    part: &Part,
+    prefix: &str,
+    doc: &mut DocState,
+    opts: &RenderOptions,
+) {
     match &part.kind {
         PartKind::Text { text, .. } => {
             if !text.is_empty() {
+                let highlighted;
+                let text = if opts.highlight.is_empty() {
+                    text
+                } else {
+                    highlighted = highlight_terms(text, &opts.highlight);
+                    &highlighted
+                };
                 // Prefix each line for blockquote nesting
                 if prefix.is_empty() {
                     writeln!(md, "{}\n", text).unwrap();
@@ -178,29 +1248,90 @@ fn render_part(md: &mut String, part: &Part, prefix: &str) {
                 }
             }
         }
-        PartKind::Tool { tool, state, .. } => {
-            render_tool(md, tool, state, prefix);
+        PartKind::Tool {
+            tool,
+            state,
+            call_id,
+        } => {
+            render_tool(md, tool, state, call_id.as_deref(), prefix, doc, opts);
+            if opts.diff_writes {
+                doc.track_file(tool, state);
+            }
+            if opts.show_metadata {
+                render_metadata(md, state.metadata.as_ref(), prefix, opts);
+            }
         }
         PartKind::StepStart { .. } => {
             // Visual step separator (subtle)
         }
-        PartKind::StepFinish { tokens, reason, .. } => {
-            // Optionally show step token counts as a small annotation
-            if let Some(t) = tokens {
-                let out = t.output.unwrap_or(0);
-                if out > 0 {
-                    let reason_str = reason.as_deref().unwrap_or("done");
+        PartKind::StepFinish {
+            tokens,
+            reason,
+            cost,
+            ..
+        } => {
+            // Optionally show step token counts as a small annotation. Reasoning is
+            // counted apart from output, so it gets its own figure.
+            let out = tokens.as_ref().and_then(|t| t.output).unwrap_or(0);
+            let reasoning = tokens.as_ref().and_then(|t| t.reasoning).unwrap_or(0);
+            doc.output += out;
+            doc.reasoning += reasoning;
+            doc.cost += cost.unwrap_or(0.0);
+            if out + reasoning > 0 {
+                // Numbered by the annotations shown; token-less steps still add their cost
+                doc.steps += 1;
+                let reason_str = reason.as_deref().unwrap_or("done");
+                if opts.cumulative_steps {
+                    let n = |v| format_number_as(v, opts.number_format);
+                    let step_reasoning = if reasoning > 0 {
+                        format!(", +{} reasoning", n(reasoning))
+                    } else {
+                        String::new()
+                    };
+                    let total_reasoning = if doc.reasoning > 0 {
+                        format!(", {} reasoning", n(doc.reasoning))
+                    } else {
+                        String::new()
+                    };
                     writeln!(
                         md,
-                        "{}*Step: {} output tokens, {}*\n",
-                        prefix, out, reason_str
+                        "{}*Step {}: +{} out{} (total {} out{}), ${:.2} cumulative, {}*\n",
+                        prefix,
+                        doc.steps,
+                        n(out),
+                        step_reasoning,
+                        n(doc.output),
+                        total_reasoning,
+                        doc.cost,
+                        reason_str
+                    )
+                    .unwrap();
+                } else {
+                    let reasoning_str = if reasoning > 0 {
+                        format!(", {} reasoning tokens", reasoning)
+                    } else {
+                        String::new()
+                    };
+                    writeln!(
+                        md,
+                        "{}*Step: {} output tokens{}, {}*\n",
+                        prefix, out, reasoning_str, reason_str
                     )
                     .unwrap();
                 }
             }
         }
-        PartKind::Reasoning { ref text, .. } => {
+        PartKind::Reasoning {
+            ref text,
+            ref metadata,
+            ..
+        } => {
             if let Some(t) = text {
+                let t = if opts.strip_signatures {
+                    strip_signatures(t)
+                } else {
+                    Cow::Borrowed(t.as_str())
+                };
                 if !t.is_empty() {
                     writeln!(md, "{}<details>", prefix).unwrap();
                     writeln!(md, "{}<summary>Thinking...</summary>\n", prefix).unwrap();
@@ -213,6 +1344,9 @@ fn render_part(md: &mut String, part: &Part, prefix: &str) {
                         writeln!(md).unwrap();
                     }
                     writeln!(md, "{}</details>\n", prefix).unwrap();
+                    if opts.show_metadata {
+                        render_metadata(md, metadata.as_ref(), prefix, opts);
+                    }
                 }
             }
         }
@@ -227,44 +1361,329 @@ fn render_part(md: &mut String, part: &Part, prefix: &str) {
                 }
             }
         }
-        PartKind::Unknown => {}
+        PartKind::Permission {
+            tool,
+            status,
+            reason,
+        } => {
+            let tool = tool.as_deref().unwrap_or("tool");
+            let status = status.as_deref().unwrap_or("requested");
+            match reason.as_deref().filter(|r| !r.is_empty()) {
+                Some(r) => writeln!(
+                    md,
+                    "{}> 🔒 Permission {} for `{}`: {}\n",
+                    prefix, status, tool, r
+                )
+                .unwrap(),
+                None => {
+                    writeln!(md, "{}> 🔒 Permission {} for `{}`\n", prefix, status, tool).unwrap()
+                }
+            }
+        }
+        PartKind::Unknown => {}
+    }
+}
+
+/// Reasoning text without signature blobs, for `--strip-reasoning-signatures`.
+///
+/// A signature is a whitespace-separated word (ignoring surrounding quotes and
+/// punctuation) of at least 100 characters, all from the base64 / base64url alphabet
+/// plus `=` padding, that mixes upper case, lower case, and digits. Such words are
+/// removed, and lines left empty by that are dropped.
+fn strip_signatures(text: &str) -> Cow<'_, str> {
+    if !text.split_whitespace().any(is_signature) {
+        return Cow::Borrowed(text);
+    }
+    let mut out = String::with_capacity(text.len());
+    for line in text.lines() {
+        if !line.split_whitespace().any(is_signature) {
+            out.push_str(line);
+            out.push('\n');
+            continue;
+        }
+        let kept: Vec<&str> = line
+            .split_whitespace()
+            .filter(|word| !is_signature(word))
+            .collect();
+        if !kept.is_empty() {
+            out.push_str(&kept.join(" "));
+            out.push('\n');
+        }
+    }
+    Cow::Owned(out.trim_end().to_string())
+}
+
+fn is_signature(word: &str) -> bool {
+    let word = word.trim_matches(|c: char| "\"'`,;:()[]{}".contains(c));
+    word.len() >= 100
+        && word
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "+/-_=".contains(c))
+        && word.chars().any(|c| c.is_ascii_uppercase())
+        && word.chars().any(|c| c.is_ascii_lowercase())
+        && word.chars().any(|c| c.is_ascii_digit())
+}
+
+/// Wrap `--highlight` matches in `**...**`, leaving fenced code blocks, inline code
+/// spans, and URLs untouched so their formatting and links survive.
+fn highlight_terms(text: &str, terms: &[String]) -> String {
+    let mut out = String::with_capacity(text.len() + 32);
+    let mut in_fence = false;
+    for line in text.split_inclusive('\n') {
+        let trimmed = line.trim_start();
+        if trimmed.starts_with("```") || trimmed.starts_with("~~~") {
+            in_fence = !in_fence;
+            out.push_str(line);
+        } else if in_fence {
+            out.push_str(line);
+        } else {
+            highlight_line(&mut out, line, terms);
+        }
+    }
+    out
+}
+
+fn highlight_line(out: &mut String, line: &str, terms: &[String]) {
+    let mut in_code = false;
+    let mut i = 0;
+    while i < line.len() {
+        let rest = &line[i..];
+        if rest.starts_with('`') {
+            in_code = !in_code;
+        } else if !in_code {
+            if rest.starts_with("http://") || rest.starts_with("https://") {
+                let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+                out.push_str(&rest[..end]);
+                i += end;
+                continue;
+            }
+            // Longest matching term wins, so `foobar` beats `foo`
+            let matched = terms
+                .iter()
+                .filter(|t| {
+                    !t.is_empty()
+                        && rest.is_char_boundary(t.len())
+                        && rest.as_bytes()[..t.len()].eq_ignore_ascii_case(t.as_bytes())
+                })
+                .map(|t| t.len())
+                .max();
+            if let Some(n) = matched {
+                write!(out, "**{}**", &rest[..n]).unwrap();
+                i += n;
+                continue;
+            }
+        }
+        let ch = rest.chars().next().unwrap();
+        out.push(ch);
+        i += ch.len_utf8();
+    }
+}
+
+/// Pretty-printed `metadata` for `--show-metadata`; null, `{}` and `[]` are skipped.
+fn render_metadata<W: Write>(
+    md: &mut W,
+    metadata: Option<&serde_json::Value>,
+    prefix: &str,
+    opts: &RenderOptions,
+) {
+    let Some(metadata) = metadata.filter(|m| match m {
+        serde_json::Value::Object(map) => !map.is_empty(),
+        serde_json::Value::Array(items) => !items.is_empty(),
+        serde_json::Value::Null => false,
+        _ => true,
+    }) else {
+        return;
+    };
+    let json = serde_json::to_string_pretty(metadata).unwrap_or_default();
+    let fence = opts.fence(&json);
+    writeln!(md, "{}*Metadata:*", prefix).unwrap();
+    writeln!(md, "{}{}json", prefix, fence).unwrap();
+    for line in json.lines() {
+        writeln!(md, "{}{}", prefix, line).unwrap();
     }
+    writeln!(md, "{}{}\n", prefix, fence).unwrap();
 }
 
-fn render_tool(md: &mut String, tool: &str, state: &ToolState, prefix: &str) {
+fn render_tool<W: Write>(
+    md: &mut W,
+    tool: &str,
+    state: &ToolState,
+    call_id: Option<&str>,
+    prefix: &str,
+    doc: &DocState,
+    opts: &RenderOptions,
+) {
     let status = state.status.as_deref().unwrap_or("unknown");
     let title = state.title.as_deref().unwrap_or(tool);
 
     // Tool header
     let status_indicator = if status == "error" { " **ERROR**" } else { "" };
-    writeln!(
-        md,
-        "{}### Tool: `{}` - {}{}\n",
-        prefix, tool, title, status_indicator
-    )
-    .unwrap();
+    let call = match call_id {
+        Some(id) if opts.show_call_ids => format!(" (call: `{}`)", short_call_id(id)),
+        _ => String::new(),
+    };
+    match mcp_tool_name(tool) {
+        Some((server, name)) => {
+            // The stored title is often just the namespaced name again
+            let title = match state.title.as_deref() {
+                Some(t) if t != tool => format!(" - {}", t),
+                _ => String::new(),
+            };
+            writeln!(
+                md,
+                "{}{} MCP Tool: {} / {}{}{}{}\n",
+                prefix,
+                opts.heading(3),
+                server,
+                name,
+                title,
+                call,
+                status_indicator
+            )
+            .unwrap();
+        }
+        None => writeln!(
+            md,
+            "{}{} Tool: `{}` - {}{}{}\n",
+            prefix,
+            opts.heading(3),
+            tool,
+            title,
+            call,
+            status_indicator
+        )
+        .unwrap(),
+    }
 
     // Input
     if let Some(ref input) = state.input {
-        render_tool_input(md, tool, input, prefix);
+        render_tool_input(md, tool, input, prefix, doc, opts);
     }
 
     // Output or Error
     if let Some(ref error) = state.error {
-        writeln!(md, "{}**Error:**", prefix).unwrap();
-        writeln!(md, "{}```", prefix).unwrap();
+        let summary = error_summary(error).filter(|_| opts.pretty_errors);
+        let fence = opts.fence(error);
+        match summary {
+            Some(ref summary) => {
+                writeln!(md, "{}**Error: {}**\n", prefix, summary).unwrap();
+                writeln!(md, "{}<details>", prefix).unwrap();
+                writeln!(
+                    md,
+                    "{}<summary>Full error ({} lines)</summary>\n",
+                    prefix,
+                    error.lines().count()
+                )
+                .unwrap();
+            }
+            None => writeln!(md, "{}**Error:**", prefix).unwrap(),
+        }
+        writeln!(md, "{}{}", prefix, fence).unwrap();
         for line in error.lines() {
-            writeln!(md, "{}{}", prefix, line).unwrap();
+            writeln!(md, "{}{}", prefix, clamp(line, opts.clamp_width)).unwrap();
+        }
+        writeln!(md, "{}{}\n", prefix, fence).unwrap();
+        if summary.is_some() {
+            writeln!(md, "{}</details>\n", prefix).unwrap();
         }
-        writeln!(md, "{}```\n", prefix).unwrap();
     } else if let Some(ref output) = state.output {
         if !output.is_empty() {
-            render_tool_output(md, tool, output, prefix);
+            render_tool_output(md, tool, output, prefix, opts);
         }
     }
 }
 
-fn render_tool_input(md: &mut String, tool: &str, input: &serde_json::Value, prefix: &str) {
+/// The line of a multi-line tool error that says what went wrong, for `--pretty-errors`.
+/// Python tracebacks end with the exception; compiler output leads with its first
+/// `error` line; anything else (including JS stacks) is summed up by its first line.
+/// `None` for one-line errors, which need no summary.
+fn error_summary(error: &str) -> Option<String> {
+    let lines: Vec<&str> = error
+        .lines()
+        .map(str::trim)
+        .filter(|l| !l.is_empty())
+        .collect();
+    if lines.len() < 2 {
+        return None;
+    }
+    let line = if lines[0].starts_with("Traceback (most recent call last)") {
+        lines[lines.len() - 1]
+    } else {
+        lines
+            .iter()
+            .find(|l| l.starts_with("error:") || l.starts_with("error["))
+            .unwrap_or(&lines[0])
+    };
+    // Keep the bold line from breaking out of its emphasis or running on
+    let line = line.replace('*', "\\*");
+    let summary = match line.char_indices().nth(160) {
+        Some((i, _)) => format!("{}…", &line[..i]),
+        None => line,
+    };
+    Some(summary)
+}
+
+/// `00012| text` → `text`; `None` for a line without a line number.
+fn strip_line_number(line: &str) -> Option<&str> {
+    let (n, rest) = line.split_once('|')?;
+    let n = n.trim_start();
+    (!n.is_empty() && n.bytes().all(|b| b.is_ascii_digit()))
+        .then(|| rest.strip_prefix(' ').unwrap_or(rest))
+}
+
+/// A `--diff-writes` view of a `write` call: a unified diff from the file's earlier
+/// content, collapsed like the full content would be.
+fn render_write_diff<W: Write>(
+    md: &mut W,
+    prior: &str,
+    content: &str,
+    prefix: &str,
+    opts: &RenderOptions,
+) {
+    // A missing final newline on either side isn't worth a hunk of its own
+    let before = format!("{}\n", prior.trim_end_matches('\n'));
+    let after = format!("{}\n", content.trim_end_matches('\n'));
+    let diff = TextDiff::from_lines(&before, &after);
+    let (adds, dels) = diff
+        .iter_all_changes()
+        .fold((0, 0), |(adds, dels), change| match change.tag() {
+            ChangeTag::Insert => (adds + 1, dels),
+            ChangeTag::Delete => (adds, dels + 1),
+            ChangeTag::Equal => (adds, dels),
+        });
+    if adds + dels == 0 {
+        writeln!(md, "{}*Same content as before*\n", prefix).unwrap();
+        return;
+    }
+    let hunks = diff.unified_diff().context_radius(3).to_string();
+    writeln!(md, "{}<details>", prefix).unwrap();
+    writeln!(
+        md,
+        "{}<summary>Changes from earlier content (+{} / -{} lines)</summary>\n",
+        prefix, adds, dels
+    )
+    .unwrap();
+    let fence = opts.fence(&hunks);
+    writeln!(md, "{}{}diff", prefix, fence).unwrap();
+    for line in hunks.lines() {
+        writeln!(md, "{}{}", prefix, clamp(line, opts.clamp_width)).unwrap();
+    }
+    writeln!(md, "{}{}\n", prefix, fence).unwrap();
+    writeln!(md, "{}</details>\n", prefix).unwrap();
+}
+
+fn render_tool_input<W: Write>(
+    md: &mut W,
+    tool: &str,
+    input: &serde_json::Value,
+    prefix: &str,
+    doc: &DocState,
+    opts: &RenderOptions,
+) {
+    if opts.raw_tool_input {
+        render_input_json(md, input, prefix, opts);
+        return;
+    }
     match tool {
         "bash" => {
             if let Some(cmd) = input.get("command").and_then(|v| v.as_str()) {
@@ -275,23 +1694,32 @@ fn render_tool_input(md: &mut String, tool: &str, input: &serde_json::Value, pre
                 if !desc.is_empty() {
                     writeln!(md, "{}> {}\n", prefix, desc).unwrap();
                 }
-                writeln!(md, "{}```bash", prefix).unwrap();
+                let fence = opts.fence(cmd);
+                writeln!(md, "{}{}bash", prefix, fence).unwrap();
                 for line in cmd.lines() {
-                    writeln!(md, "{}{}", prefix, line).unwrap();
+                    writeln!(md, "{}{}", prefix, clamp(line, opts.clamp_width)).unwrap();
                 }
-                writeln!(md, "{}```\n", prefix).unwrap();
+                writeln!(md, "{}{}\n", prefix, fence).unwrap();
             }
         }
         "read" => {
             if let Some(path) = input.get("filePath").and_then(|v| v.as_str()) {
-                writeln!(md, "{}**File:** `{}`\n", prefix, path).unwrap();
+                writeln!(md, "{}**File:** {}\n", prefix, file_ref(path, opts)).unwrap();
             }
         }
         "write" => {
-            if let Some(path) = input.get("filePath").and_then(|v| v.as_str()) {
-                writeln!(md, "{}**Write to:** `{}`\n", prefix, path).unwrap();
+            let path = input.get("filePath").and_then(|v| v.as_str());
+            if let Some(path) = path {
+                writeln!(md, "{}**Write to:** {}\n", prefix, file_ref(path, opts)).unwrap();
             }
-            if let Some(content) = input.get("content").and_then(|v| v.as_str()) {
+            let prior = path
+                .filter(|_| opts.diff_writes)
+                .and_then(|path| doc.prior_content(path))
+                .filter(|prior| !prior.is_empty());
+            let content = input.get("content").and_then(|v| v.as_str());
+            if let (Some(prior), Some(content)) = (prior, content) {
+                render_write_diff(md, prior, content, prefix, opts);
+            } else if let Some(content) = content {
                 let ext = input
                     .get("filePath")
                     .and_then(|v| v.as_str())
@@ -305,29 +1733,31 @@ fn render_tool_input(md: &mut String, tool: &str, input: &serde_json::Value, pre
                     content.lines().count()
                 )
                 .unwrap();
-                writeln!(md, "{}```{}", prefix, ext).unwrap();
+                let fence = opts.fence(content);
+                writeln!(md, "{}{}{}", prefix, fence, ext).unwrap();
                 for line in content.lines() {
-                    writeln!(md, "{}{}", prefix, line).unwrap();
+                    writeln!(md, "{}{}", prefix, clamp(line, opts.clamp_width)).unwrap();
                 }
-                writeln!(md, "{}```\n", prefix).unwrap();
+                writeln!(md, "{}{}\n", prefix, fence).unwrap();
                 writeln!(md, "{}</details>\n", prefix).unwrap();
             }
         }
         "edit" => {
             if let Some(path) = input.get("filePath").and_then(|v| v.as_str()) {
-                writeln!(md, "{}**Edit:** `{}`\n", prefix, path).unwrap();
+                writeln!(md, "{}**Edit:** {}\n", prefix, file_ref(path, opts)).unwrap();
             }
             if let Some(old) = input.get("oldString").and_then(|v| v.as_str()) {
-                writeln!(md, "{}```diff", prefix).unwrap();
+                let new = input.get("newString").and_then(|v| v.as_str());
+                // Every line starts with `- ` or `+ `, so the content can't close the fence
+                let fence = opts.fence("");
+                writeln!(md, "{}{}diff", prefix, fence).unwrap();
                 for line in old.lines() {
-                    writeln!(md, "{}- {}", prefix, line).unwrap();
+                    writeln!(md, "{}- {}", prefix, clamp(line, opts.clamp_width)).unwrap();
                 }
-                if let Some(new) = input.get("newString").and_then(|v| v.as_str()) {
-                    for line in new.lines() {
-                        writeln!(md, "{}+ {}", prefix, line).unwrap();
-                    }
+                for line in new.into_iter().flat_map(str::lines) {
+                    writeln!(md, "{}+ {}", prefix, clamp(line, opts.clamp_width)).unwrap();
                 }
-                writeln!(md, "{}```\n", prefix).unwrap();
+                writeln!(md, "{}{}\n", prefix, fence).unwrap();
             }
         }
         "glob" => {
@@ -342,23 +1772,54 @@ fn render_tool_input(md: &mut String, tool: &str, input: &serde_json::Value, pre
                 writeln!(md, "{}**Search:** `{}` in `{}`\n", prefix, pattern, path).unwrap();
             }
         }
+        "task" => {
+            // The sub-agent conversation itself is inlined by the resolver; only show the brief
+            if let Some(agent) = input.get("subagent_type").and_then(|v| v.as_str()) {
+                writeln!(md, "{}**Agent:** `{}`\n", prefix, agent).unwrap();
+            }
+            let brief = input
+                .get("prompt")
+                .or_else(|| input.get("description"))
+                .and_then(|v| v.as_str())
+                .unwrap_or("");
+            if !brief.is_empty() {
+                for line in brief.lines() {
+                    writeln!(md, "{}> {}", prefix, line).unwrap();
+                }
+                writeln!(md).unwrap();
+            }
+        }
         "todowrite" | "todoread" => {
             // Skip rendering todo tool calls — they show up in the task list section
         }
-        _ => {
-            // Generic: dump input as JSON
-            if let Ok(pretty) = serde_json::to_string_pretty(input) {
-                writeln!(md, "{}```json", prefix).unwrap();
-                for line in pretty.lines() {
-                    writeln!(md, "{}{}", prefix, line).unwrap();
-                }
-                writeln!(md, "{}```\n", prefix).unwrap();
-            }
+        _ => render_input_json(md, input, prefix, opts),
+    }
+}
+
+/// Generic tool input view: the whole input as pretty-printed JSON.
+fn render_input_json<W: Write>(
+    md: &mut W,
+    input: &serde_json::Value,
+    prefix: &str,
+    opts: &RenderOptions,
+) {
+    if let Ok(pretty) = serde_json::to_string_pretty(input) {
+        let fence = opts.fence(&pretty);
+        writeln!(md, "{}{}json", prefix, fence).unwrap();
+        for line in pretty.lines() {
+            writeln!(md, "{}{}", prefix, clamp(line, opts.clamp_width)).unwrap();
         }
+        writeln!(md, "{}{}\n", prefix, fence).unwrap();
     }
 }
 
-fn render_tool_output(md: &mut String, tool: &str, output: &str, prefix: &str) {
+fn render_tool_output<W: Write>(
+    md: &mut W,
+    tool: &str,
+    output: &str,
+    prefix: &str,
+    opts: &RenderOptions,
+) {
     // For write tool, output is often diagnostics — wrap in details
     // For read tool, output can be very long — wrap in details
     let wrap_in_details = matches!(tool, "write" | "read") && output.lines().count() > 30;
@@ -376,59 +1837,679 @@ fn render_tool_output(md: &mut String, tool: &str, output: &str, prefix: &str) {
         writeln!(md, "{}**Output:**", prefix).unwrap();
     }
 
-    writeln!(md, "{}```", prefix).unwrap();
+    let fence = opts.fence(output);
+    writeln!(md, "{}{}", prefix, fence).unwrap();
     for line in output.lines() {
-        writeln!(md, "{}{}", prefix, line).unwrap();
+        writeln!(md, "{}{}", prefix, clamp(line, opts.clamp_width)).unwrap();
     }
-    writeln!(md, "{}```\n", prefix).unwrap();
+    writeln!(md, "{}{}\n", prefix, fence).unwrap();
 
     if wrap_in_details {
         writeln!(md, "{}</details>\n", prefix).unwrap();
     }
 }
 
+/// Server and tool of an MCP tool namespaced as `mcp__<server>__<tool>`.
+fn mcp_tool_name(tool: &str) -> Option<(&str, &str)> {
+    let (server, name) = tool.strip_prefix("mcp__")?.split_once("__")?;
+    (!server.is_empty() && !name.is_empty()).then_some((server, name))
+}
+
+/// A tool's file path as a code span, linked with `--link-files`.
+fn file_ref(path: &str, opts: &RenderOptions) -> String {
+    match opts.link_files {
+        Some(ref worktree) => format!(
+            "[`{}`]({})",
+            path,
+            file_url(path, worktree, opts.anonymize.as_ref())
+        ),
+        None => format!("`{}`", path),
+    }
+}
+
+/// `file://` URL for `path`; a relative path is taken to be inside `worktree`.
+///
+/// With `--anonymize` the path is scrubbed first and the scheme left off, since
+/// `file://~/...` is no URL: the target is then a plain `~/src/app.rs` style path.
+fn file_url(path: &str, worktree: &str, anonymize: Option<&Anonymize>) -> String {
+    let is_windows_abs = path.as_bytes().get(1) == Some(&b':');
+    let absolute = if path.starts_with('/') || path.starts_with('\\') || is_windows_abs {
+        path.to_string()
+    } else {
+        format!(
+            "{}/{}",
+            worktree.trim_end_matches(['/', '\\']),
+            path.trim_start_matches("./")
+        )
+    };
+    let absolute = match anonymize {
+        Some(anon) => anon.scrub(&absolute, worktree),
+        None => absolute,
+    };
+    let absolute = absolute.replace('\\', "/");
+    let mut url = String::new();
+    if anonymize.is_none() {
+        url.push_str("file://");
+        // `C:/x` becomes `file:///C:/x`
+        if !absolute.starts_with('/') {
+            url.push('/');
+        }
+    }
+    for b in absolute.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'/' | b'-' | b'_' | b'.' | b'~' | b':' => {
+                url.push(b as char)
+            }
+            _ => url.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    url
+}
+
+/// First 24 characters of a tool call ID, ending in `…` when cut. That keeps enough of
+/// the random part of `toolu_…` / `call_…` IDs to search logs for.
+fn short_call_id(id: &str) -> Cow<'_, str> {
+    if id.chars().count() > 24 {
+        Cow::Owned(format!("{}…", id.chars().take(24).collect::<String>()))
+    } else {
+        Cow::Borrowed(id)
+    }
+}
+
+/// Keep at most two blank lines in a row, for `--trim-empty-lines`. Lines inside code
+/// fences (including fences in `> ` sub-agent quotes) are left alone.
+fn trim_empty_lines(md: &str) -> String {
+    let mut out = String::with_capacity(md.len());
+    // Fence character and length of the open code block, if any
+    let mut fence: Option<(char, usize)> = None;
+    let mut blanks = 0;
+    for line in md.split_inclusive('\n') {
+        let body = line.trim_start_matches(['>', ' ']).trim_end();
+        let run = body.chars().take_while(|&c| c == '`' || c == '~').count();
+        let ch = body.chars().next().unwrap_or(' ');
+        if run >= 3 && body.chars().take(run).all(|c| c == ch) {
+            fence = match fence {
+                None => Some((ch, run)),
+                Some((open, len)) if open == ch && run >= len && body.len() == run => None,
+                open => open,
+            };
+        }
+        if fence.is_none() && line.trim().is_empty() {
+            blanks += 1;
+            if blanks > 2 {
+                continue;
+            }
+        } else {
+            blanks = 0;
+        }
+        out.push_str(line);
+    }
+    out
+}
+
+/// Cut `line` to `width` characters, ending in `…`, for `--clamp-width`. 0 means no limit.
+fn clamp(line: &str, width: usize) -> Cow<'_, str> {
+    if width == 0 || line.chars().count() <= width {
+        return Cow::Borrowed(line);
+    }
+    let mut cut: String = line.chars().take(width - 1).collect();
+    cut.push('…');
+    Cow::Owned(cut)
+}
+
 // ── Sub-agent rendering ─────────────────────────────────────────────
 
-fn render_sub_agent(
-    md: &mut String,
+fn render_sub_agent<W: Write>(
+    md: &mut W,
     session: &Session,
     messages: &[ResolvedConversationItem],
-    depth: usize,
+    doc: &mut DocState,
+    opts: &RenderOptions,
 ) {
     let title = session.title.as_deref().unwrap_or("Sub-agent");
     let agent_type = session.slug.as_deref().unwrap_or("agent");
+    let prefix = if opts.flatten_subagents { "" } else { "> " };
 
     writeln!(md, "---\n").unwrap();
-    writeln!(md, "> ### Sub-agent: {} (`{}`)\n", title, agent_type).unwrap();
+    writeln!(
+        md,
+        "{}{} Sub-agent: {} (`{}`)\n",
+        prefix,
+        opts.heading(3),
+        title,
+        agent_type
+    )
+    .unwrap();
 
-    render_conversation_items(md, messages, depth + 1);
+    render_conversation_items(md, messages, Some(title), doc, opts);
 
-    writeln!(md, "> *End of sub-agent*\n").unwrap();
+    writeln!(md, "{}*End of sub-agent*\n", prefix).unwrap();
     writeln!(md, "---\n").unwrap();
 }
 
+// ── Compact transcript ──────────────────────────────────────────────
+
+/// Render a session as one line per turn (`[U]`, `[A]`, `[T:<tool>]`) for skimming and grepping.
+pub fn render_session_compact(
+    resolved: &ResolvedSession,
+    project: &Project,
+    opts: &RenderOptions,
+) -> String {
+    let mut out = String::with_capacity(8192);
+    let derived = opts
+        .derive_titles
+        .then(|| resolved.derived_title())
+        .flatten();
+    let title = resolved
+        .session
+        .title
+        .as_deref()
+        .or(derived.as_deref())
+        .unwrap_or("Untitled Session");
+    writeln!(
+        out,
+        "{} {} ({})\n",
+        opts.heading(1),
+        title,
+        format_timestamp(resolved.session.time.created)
+    )
+    .unwrap();
+    if resolved.omitted_before > 0 {
+        writeln!(
+            out,
+            "> ... ({} earlier messages omitted) ...",
+            resolved.omitted_before
+        )
+        .unwrap();
+    }
+    render_compact_items(&mut out, &resolved.messages, 0, opts);
+    if resolved.omitted_items > 0 {
+        writeln!(
+            out,
+            "> ... ({} later messages omitted) ...",
+            resolved.omitted_items
+        )
+        .unwrap();
+    }
+
+    match opts.anonymize {
+        Some(ref anon) => anon.apply(&out, project),
+        None => out,
+    }
+}
+
+/// The session's own user prompts as plain text, one entry per message, for `--prompt-only`.
+///
+/// Only text parts are kept. Sub-agent prompts are written by the parent agent rather than
+/// the user, so they are skipped.
+pub fn session_prompts(
+    resolved: &ResolvedSession,
+    project: &Project,
+    opts: &RenderOptions,
+) -> Vec<String> {
+    resolved
+        .messages
+        .iter()
+        .filter_map(|item| match item {
+            ResolvedConversationItem::Message(rm) if rm.message.role == "user" => {
+                let text = rm
+                    .parts
+                    .iter()
+                    .filter_map(|p| match &p.kind {
+                        PartKind::Text { text, .. } => Some(text.trim()),
+                        _ => None,
+                    })
+                    .filter(|t| !t.is_empty())
+                    .collect::<Vec<_>>()
+                    .join("\n\n");
+                if text.is_empty() {
+                    return None;
+                }
+                Some(match opts.anonymize {
+                    Some(ref anon) => anon.apply(&text, project),
+                    None => text,
+                })
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+fn render_compact_items(
+    out: &mut String,
+    items: &[ResolvedConversationItem],
+    depth: usize,
+    opts: &RenderOptions,
+) {
+    let prefix = if depth > 0 {
+        format!("{}[sub] ", "  ".repeat(depth))
+    } else {
+        String::new()
+    };
+    for item in items {
+        match item {
+            ResolvedConversationItem::Message(rm) => {
+                if !opts.shows_role(&rm.message.role) {
+                    continue;
+                }
+                let tag = if rm.message.role == "user" { "U" } else { "A" };
+                let text = rm.parts.iter().find_map(|p| match &p.kind {
+                    PartKind::Text { text, .. } if !text.trim().is_empty() => Some(text),
+                    _ => None,
+                });
+                if let Some(text) = text {
+                    writeln!(out, "{}[{}] {}", prefix, tag, one_line(text, 80)).unwrap();
+                }
+                for part in &rm.parts {
+                    if let PartKind::Tool { tool, state, .. } = &part.kind {
+                        if matches!(tool.as_str(), "todowrite" | "todoread") {
+                            continue;
+                        }
+                        writeln!(
+                            out,
+                            "{}[T:{}] {}",
+                            prefix,
+                            tool,
+                            one_line(&tool_summary(tool, state), 80)
+                        )
+                        .unwrap();
+                    }
+                }
+            }
+            ResolvedConversationItem::SubAgent { session, messages } => {
+                let title = session.title.as_deref().unwrap_or("Sub-agent");
+                writeln!(out, "{}[sub] {}", "  ".repeat(depth), title).unwrap();
+                render_compact_items(out, messages, depth + 1, opts);
+            }
+        }
+    }
+}
+
+/// The most telling single value of a tool call's input, falling back to its title.
+fn tool_summary(tool: &str, state: &ToolState) -> String {
+    let key = match tool {
+        "bash" => "command",
+        "read" | "write" | "edit" => "filePath",
+        "glob" | "grep" => "pattern",
+        "task" => "description",
+        _ => "",
+    };
+    state
+        .input
+        .as_ref()
+        .and_then(|input| input.get(key))
+        .and_then(|v| v.as_str())
+        .or(state.title.as_deref())
+        .unwrap_or("")
+        .to_string()
+}
+
+// ── Plain text ──────────────────────────────────────────────────────
+
+/// Render a session without any Markdown, for `--format text`.
+///
+/// Turns start with `USER:` / `ASSISTANT (<model>):`. Tool calls, their output, and
+/// reasoning are indented blocks beneath the turn; sub-agent turns are indented one
+/// level further.
+pub fn render_session_text(
+    resolved: &ResolvedSession,
+    project: &Project,
+    opts: &RenderOptions,
+) -> String {
+    let mut out = String::with_capacity(8192);
+    let derived = opts
+        .derive_titles
+        .then(|| resolved.derived_title())
+        .flatten();
+    let title = resolved
+        .session
+        .title
+        .as_deref()
+        .or(derived.as_deref())
+        .unwrap_or("Untitled Session");
+    writeln!(out, "{}\n", title).unwrap();
+    writeln!(out, "Project: {}", project.worktree).unwrap();
+    writeln!(
+        out,
+        "Date:    {}",
+        format_timestamp(resolved.session.time.created)
+    )
+    .unwrap();
+    writeln!(out, "Session: {}\n", resolved.session.id).unwrap();
+    if resolved.omitted_before > 0 {
+        writeln!(
+            out,
+            "... ({} earlier messages omitted) ...\n",
+            resolved.omitted_before
+        )
+        .unwrap();
+    }
+    render_text_items(&mut out, &resolved.messages, 0, opts);
+    if resolved.omitted_items > 0 {
+        writeln!(
+            out,
+            "... ({} later messages omitted) ...",
+            resolved.omitted_items
+        )
+        .unwrap();
+    }
+
+    match opts.anonymize {
+        Some(ref anon) => anon.apply(&out, project),
+        None => out,
+    }
+}
+
+fn render_text_items(
+    out: &mut String,
+    items: &[ResolvedConversationItem],
+    depth: usize,
+    opts: &RenderOptions,
+) {
+    let pad = "    ".repeat(depth);
+    for item in items {
+        match item {
+            ResolvedConversationItem::Message(rm) => {
+                let role = &rm.message.role;
+                if !opts.shows_role(role) {
+                    continue;
+                }
+                let has_content = rm.parts.iter().any(part_has_content);
+                if !has_content && opts.empty_messages == EmptyMessages::Skip {
+                    continue;
+                }
+                if role == "assistant" {
+                    let model = opts
+                        .model_label(&rm.message)
+                        .unwrap_or(Cow::Borrowed("assistant"));
+                    writeln!(out, "{}ASSISTANT ({}):", pad, model).unwrap();
+                } else {
+                    writeln!(out, "{}{}:", pad, role.to_uppercase()).unwrap();
+                }
+                if !has_content {
+                    writeln!(out, "{}(no content)\n", pad).unwrap();
+                }
+                for part in &rm.parts {
+                    render_text_part(out, part, &pad, opts);
+                }
+            }
+            ResolvedConversationItem::SubAgent { session, messages } => {
+                let title = session.title.as_deref().unwrap_or("Sub-agent");
+                writeln!(out, "{}SUB-AGENT: {}\n", pad, title).unwrap();
+                render_text_items(out, messages, depth + 1, opts);
+            }
+        }
+    }
+}
+
+/// Write one part as a block followed by a blank line; parts with nothing to show write nothing.
+fn render_text_part(out: &mut String, part: &Part, pad: &str, opts: &RenderOptions) {
+    let inner = format!("{}    ", pad);
+    let deeper = format!("{}    ", inner);
+    match &part.kind {
+        PartKind::Text { text, .. } if !text.trim().is_empty() => {
+            for line in text.trim_end().lines() {
+                write_indented(out, pad, line);
+            }
+        }
+        PartKind::Tool { tool, state, .. } => {
+            let title = state.title.as_deref().unwrap_or(tool);
+            let error = if state.status.as_deref() == Some("error") {
+                " [ERROR]"
+            } else {
+                ""
+            };
+            writeln!(out, "{}Tool: {} - {}{}", inner, tool, title, error).unwrap();
+            if let Some(ref input) = state.input {
+                render_text_input(out, input, &inner);
+            }
+            let body = match (&state.error, &state.output) {
+                (Some(error), _) => Some(("Error", error)),
+                (None, Some(output)) if !output.is_empty() => Some(("Output", output)),
+                _ => None,
+            };
+            if let Some((label, body)) = body {
+                writeln!(out, "{}{}:", inner, label).unwrap();
+                for line in body.lines() {
+                    write_indented(out, &deeper, &clamp(line, opts.clamp_width));
+                }
+            }
+        }
+        PartKind::Reasoning { text: Some(t), .. } => {
+            let t = if opts.strip_signatures {
+                strip_signatures(t)
+            } else {
+                Cow::Borrowed(t.as_str())
+            };
+            if t.trim().is_empty() {
+                return;
+            }
+            writeln!(out, "{}Thinking:", inner).unwrap();
+            for line in t.trim_end().lines() {
+                write_indented(out, &deeper, line);
+            }
+        }
+        PartKind::Patch { files: Some(f), .. } if !f.is_empty() => {
+            writeln!(out, "{}Patched files: {}", inner, f.join(", ")).unwrap();
+        }
+        PartKind::Permission {
+            tool,
+            status,
+            reason,
+        } => {
+            let tool = tool.as_deref().unwrap_or("tool");
+            let status = status.as_deref().unwrap_or("requested");
+            match reason.as_deref().filter(|r| !r.is_empty()) {
+                Some(r) => writeln!(out, "{}Permission {} for {}: {}", inner, status, tool, r),
+                None => writeln!(out, "{}Permission {} for {}", inner, status, tool),
+            }
+            .unwrap();
+        }
+        _ => return,
+    }
+    writeln!(out).unwrap();
+}
+
+/// `line` behind `pad`; blank lines get no trailing whitespace.
+fn write_indented(out: &mut String, pad: &str, line: &str) {
+    if line.is_empty() {
+        writeln!(out).unwrap();
+    } else {
+        writeln!(out, "{}{}", pad, line).unwrap();
+    }
+}
+
+/// Tool input as `key: value` lines; multi-line strings go on their own indented lines.
+fn render_text_input(out: &mut String, input: &serde_json::Value, pad: &str) {
+    let Some(fields) = input.as_object() else {
+        writeln!(out, "{}{}", pad, input).unwrap();
+        return;
+    };
+    for (key, value) in fields {
+        match value.as_str() {
+            Some(s) if s.contains('\n') => {
+                writeln!(out, "{}{}:", pad, key).unwrap();
+                for line in s.lines() {
+                    write_indented(out, &format!("{}    ", pad), line);
+                }
+            }
+            Some(s) => writeln!(out, "{}{}: {}", pad, key, s).unwrap(),
+            None => writeln!(out, "{}{}: {}", pad, key, value).unwrap(),
+        }
+    }
+}
+
+// ── Patch export ────────────────────────────────────────────────────
+
+/// Unified diff of every session diff entry that carries `before`/`after` content.
+///
+/// Returns `None` when no entry has both. Paths use git's `a/` / `b/` prefixes so
+/// the result works with `git apply` from the project root. With `--anonymize` the
+/// patch is scrubbed like the session file.
+pub fn render_patch(
+    resolved: &ResolvedSession,
+    project: &Project,
+    opts: &RenderOptions,
+) -> Option<String> {
+    let mut patch = String::new();
+    for diff in &resolved.diffs {
+        let (Some(before), Some(after)) = (diff.before.as_deref(), diff.after.as_deref()) else {
+            continue;
+        };
+        let status = diff.status.as_deref().unwrap_or("modified");
+        let old_path = if status == "added" {
+            "/dev/null".to_string()
+        } else {
+            format!("a/{}", diff.file)
+        };
+        let new_path = if status == "deleted" {
+            "/dev/null".to_string()
+        } else {
+            format!("b/{}", diff.file)
+        };
+        let text_diff = TextDiff::from_lines(before, after);
+        let hunks = text_diff
+            .unified_diff()
+            .context_radius(3)
+            .header(&old_path, &new_path)
+            .to_string();
+        if !hunks.is_empty() {
+            writeln!(patch, "diff --git a/{} b/{}", diff.file, diff.file).unwrap();
+            patch.push_str(&hunks);
+        }
+    }
+    if patch.is_empty() {
+        return None;
+    }
+    Some(match opts.anonymize {
+        Some(ref anon) => anon.apply(&patch, project),
+        None => patch,
+    })
+}
+
 // ── Utility ─────────────────────────────────────────────────────────
 
 fn format_timestamp(ts: Option<u64>) -> String {
     match ts {
-        Some(ms) => {
-            let secs = (ms / 1000) as i64;
-            let nanos = ((ms % 1000) * 1_000_000) as u32;
-            match Utc.timestamp_opt(secs, nanos) {
-                chrono::LocalResult::Single(dt) => dt.format("%Y-%m-%d %H:%M UTC").to_string(),
-                _ => format!("{}ms", ms),
-            }
-        }
+        Some(ts) => match to_datetime(ts) {
+            Some(dt) => dt.format("%Y-%m-%d %H:%M UTC").to_string(),
+            None => format!("{}ms", ts),
+        },
         None => "unknown".to_string(),
     }
 }
 
-fn format_number(n: u64) -> String {
-    if n >= 1_000_000 {
-        format!("{:.1}M", n as f64 / 1_000_000.0)
-    } else if n >= 1_000 {
-        format!("{:.1}K", n as f64 / 1_000.0)
+/// `45s`, `2m30s`, `1h05m`: the largest two units of a `--relative-times` offset.
+fn format_offset(ms: u64) -> String {
+    let secs = ms / 1000;
+    match (secs / 3600, secs / 60 % 60, secs % 60) {
+        (0, 0, s) => format!("{}s", s),
+        (0, m, s) => format!("{}m{:02}s", m, s),
+        (h, m, _) => format!("{}h{:02}m", h, m),
+    }
+}
+
+/// Words in text and reasoning parts, sub-agents included.
+fn word_count(items: &[ResolvedConversationItem]) -> usize {
+    items
+        .iter()
+        .map(|item| match item {
+            ResolvedConversationItem::Message(rm) => rm
+                .parts
+                .iter()
+                .map(|p| match &p.kind {
+                    PartKind::Text { text, .. } => text.split_whitespace().count(),
+                    PartKind::Reasoning {
+                        text: Some(text), ..
+                    } => text.split_whitespace().count(),
+                    _ => 0,
+                })
+                .sum(),
+            ResolvedConversationItem::SubAgent { messages, .. } => word_count(messages),
+        })
+        .sum()
+}
+
+/// Reading time at ~200 words per minute, rounded up.
+fn reading_time(words: usize) -> String {
+    if words < 200 {
+        "<1 min".to_string()
+    } else {
+        format!("{} min", words.div_ceil(200))
+    }
+}
+
+/// 20-block bar with a percentage, e.g. `█████░░░░░░░░░░░░░░░  25%`.
+fn token_bar(n: u64, total: u64) -> String {
+    let frac = if total > 0 {
+        n as f64 / total as f64
     } else {
-        n.to_string()
+        0.0
+    };
+    let filled = (frac * 20.0).round() as usize;
+    format!(
+        "{}{} {:>3}%",
+        "█".repeat(filled),
+        "░".repeat(20 - filled),
+        (frac * 100.0).round() as u64
+    )
+}
+
+pub fn format_number(n: u64) -> String {
+    format_number_as(n, NumberFormat::Abbrev)
+}
+
+/// `format_number` in the given style.
+pub fn format_number_as(n: u64, style: NumberFormat) -> String {
+    match style {
+        NumberFormat::Abbrev if n >= 1_000_000 => format!("{:.1}M", n as f64 / 1_000_000.0),
+        NumberFormat::Abbrev if n >= 1_000 => format!("{:.1}K", n as f64 / 1_000.0),
+        NumberFormat::Grouped => {
+            let digits = n.to_string();
+            let mut out = String::with_capacity(digits.len() + digits.len() / 3);
+            for (i, c) in digits.chars().enumerate() {
+                if i > 0 && (digits.len() - i).is_multiple_of(3) {
+                    out.push(',');
+                }
+                out.push(c);
+            }
+            out
+        }
+        _ => n.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fence_outgrows_backtick_runs() {
+        let opts = RenderOptions::default();
+        assert_eq!(opts.fence("plain"), "```");
+        assert_eq!(opts.fence("a\n```\nb\n```"), "````");
+        assert_eq!(opts.fence("`````"), "``````");
+    }
+
+    #[test]
+    fn auto_fence_switches_to_tildes() {
+        let opts = RenderOptions {
+            fence: FenceStyle::Auto,
+            ..Default::default()
+        };
+        assert_eq!(opts.fence("a\n```\nb"), "~~~");
+        assert_eq!(opts.fence("a\n```\n~~~~"), "~~~~~");
+        assert_eq!(opts.fence("plain"), "```");
+    }
+
+    #[test]
+    fn mcp_tool_names_split_into_server_and_tool() {
+        assert_eq!(
+            mcp_tool_name("mcp__github__create_issue"),
+            Some(("github", "create_issue"))
+        );
+        assert_eq!(mcp_tool_name("mcp__github"), None);
+        assert_eq!(mcp_tool_name("mcp____tool"), None);
+        assert_eq!(mcp_tool_name("bash"), None);
     }
 }
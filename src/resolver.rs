@@ -1,26 +1,51 @@
-use std::collections::HashMap;
+use regex::Regex;
+use std::collections::{HashMap, HashSet};
 
 use crate::loader::StorageData;
 use crate::types::*;
 
+/// Which sessions `resolve` keeps. Fields left as `None` don't filter.
+#[derive(Debug, Default)]
+pub struct Filters<'a> {
+    /// Worktree substring, project ID prefix, or display name
+    pub project: Option<&'a str>,
+    /// Exact project ID
+    pub project_id: Option<&'a str>,
+    /// Exact session ID; may name a sub-agent session, which is then resolved on its own
+    pub session: Option<&'a str>,
+    /// Pattern that the session ID, slug, or title must match
+    pub session_regex: Option<&'a Regex>,
+    /// Earliest `time.created` (epoch ms)
+    pub since_ms: Option<u64>,
+    /// `time.created` must be before this (epoch ms)
+    pub until_ms: Option<u64>,
+    /// Agent mode that at least one assistant message must use
+    pub mode: Option<&'a str>,
+    /// Drop sessions that spawned sub-agents
+    pub leaf_only: bool,
+}
+
+/// Whether `project` matches a `--project` value: a worktree substring, project ID
+/// prefix, or display name (case-insensitive).
+pub fn project_matches(project: &Project, filter: &str) -> bool {
+    project.worktree.contains(filter)
+        || project.id.starts_with(filter)
+        || project.display_name().eq_ignore_ascii_case(filter)
+}
+
 /// Build fully resolved projects from raw storage data.
-pub fn resolve(
-    data: &StorageData,
-    project_filter: Option<&str>,
-    session_filter: Option<&str>,
-    since_ms: Option<u64>,
-) -> Vec<ResolvedProject> {
+pub fn resolve(data: &StorageData, filters: &Filters) -> Vec<ResolvedProject> {
     let mut result = Vec::new();
 
     for project in &data.projects {
-        // Apply project filter (match on worktree path or project id)
-        if let Some(filter) = project_filter {
-            let matches = project.worktree.contains(filter)
-                || project.id.starts_with(filter)
-                || project.display_name().eq_ignore_ascii_case(filter);
-            if !matches {
-                continue;
-            }
+        if filters
+            .project
+            .is_some_and(|filter| !project_matches(project, filter))
+        {
+            continue;
+        }
+        if filters.project_id.is_some_and(|id| project.id != id) {
+            continue;
         }
 
         let session_ids = match data.sessions_by_project.get(&project.id) {
@@ -36,7 +61,7 @@ pub fn resolve(
         all_sessions.sort_by_key(|s| s.time.created.unwrap_or(0));
 
         // Build a set of sub-agent session IDs (those with a parentID)
-        let sub_agent_ids: std::collections::HashSet<&str> = all_sessions
+        let sub_agent_ids: HashSet<&str> = all_sessions
             .iter()
             .filter(|s| s.parent_id.is_some())
             .map(|s| s.id.as_str())
@@ -53,21 +78,52 @@ pub fn resolve(
         let mut resolved_sessions = Vec::new();
 
         for session in &all_sessions {
-            // Skip sub-agent sessions at the top level (they'll be inlined)
-            if sub_agent_ids.contains(session.id.as_str()) {
+            // Skip sub-agent sessions at the top level (they'll be inlined), unless one
+            // is asked for by ID: then it is exported on its own
+            if sub_agent_ids.contains(session.id.as_str())
+                && filters.session != Some(session.id.as_str())
+            {
                 continue;
             }
 
             // Apply session filter
-            if let Some(filter) = session_filter {
+            if let Some(filter) = filters.session {
                 if session.id != filter {
                     continue;
                 }
             }
+            if let Some(re) = filters.session_regex {
+                let matches = re.is_match(&session.id)
+                    || session.slug.as_deref().is_some_and(|s| re.is_match(s))
+                    || session.title.as_deref().is_some_and(|t| re.is_match(t));
+                if !matches {
+                    continue;
+                }
+            }
 
             // Apply date filter
-            if let Some(since) = since_ms {
-                if session.time.created.unwrap_or(0) < since {
+            let created = to_millis(session.time.created.unwrap_or(0));
+            if filters.since_ms.is_some_and(|since| created < since) {
+                continue;
+            }
+            if filters.until_ms.is_some_and(|until| created >= until) {
+                continue;
+            }
+
+            if filters.leaf_only && children_by_parent.contains_key(session.id.as_str()) {
+                continue;
+            }
+
+            // Apply agent mode filter
+            if let Some(mode) = filters.mode {
+                let used = data
+                    .messages_by_session
+                    .get(&session.id)
+                    .is_some_and(|msgs| {
+                        msgs.iter()
+                            .any(|m| m.role == "assistant" && m.mode.as_deref() == Some(mode))
+                    });
+                if !used {
                     continue;
                 }
             }
@@ -87,6 +143,20 @@ pub fn resolve(
     result
 }
 
+/// Resolve a single session by ID, inlining its sub-agents. Works for sub-agent sessions too.
+pub fn resolve_one(data: &StorageData, session_id: &str) -> Option<ResolvedSession> {
+    let session = data.sessions.get(session_id)?;
+
+    let mut children_by_parent: HashMap<&str, Vec<&Session>> = HashMap::new();
+    for s in data.sessions.values() {
+        if let Some(ref pid) = s.parent_id {
+            children_by_parent.entry(pid.as_str()).or_default().push(s);
+        }
+    }
+
+    Some(resolve_session(session, data, &children_by_parent))
+}
+
 fn resolve_session(
     session: &Session,
     data: &StorageData,
@@ -118,8 +188,12 @@ fn resolve_session(
         .cloned()
         .unwrap_or_default();
 
-    // Sum up tokens across all assistant messages
+    // Sum up tokens and cost across all assistant messages
     let token_totals = sum_tokens(&messages);
+    // `+ 0.0`: an empty float sum is -0.0, which would print as `$-0.00`
+    let cost_total = messages.iter().filter_map(|m| m.cost).sum::<f64>() + 0.0;
+
+    let first_prompt = first_prompt(&conversation);
 
     ResolvedSession {
         session: session.clone(),
@@ -127,7 +201,45 @@ fn resolve_session(
         diffs,
         todos,
         token_totals,
+        cost_total,
+        orphan_parts: Vec::new(),
+        omitted_items: 0,
+        omitted_before: 0,
+        ancestors: ancestors(session, data),
+        first_prompt,
+    }
+}
+
+/// The first user text of the conversation as resolved (oldest first), whitespace
+/// collapsed and cut to 60 characters.
+fn first_prompt(items: &[ResolvedConversationItem]) -> Option<String> {
+    let text = items.iter().find_map(|item| match item {
+        ResolvedConversationItem::Message(rm) if rm.message.role == "user" => {
+            rm.parts.iter().find_map(|p| match &p.kind {
+                PartKind::Text { text, .. } if !text.trim().is_empty() => Some(text.as_str()),
+                _ => None,
+            })
+        }
+        _ => None,
+    })?;
+    Some(one_line(text, 60))
+}
+
+/// `session`'s parent chain, nearest first. Stops at a missing parent or a cycle.
+fn ancestors(session: &Session, data: &StorageData) -> Vec<Session> {
+    let mut chain: Vec<Session> = Vec::new();
+    let mut next = session.parent_id.as_deref();
+    while let Some(id) = next {
+        if id == session.id || chain.iter().any(|s| s.id == id) {
+            break;
+        }
+        let Some(parent) = data.sessions.get(id) else {
+            break;
+        };
+        chain.push(parent.clone());
+        next = parent.parent_id.as_deref();
     }
+    chain
 }
 
 fn build_conversation(
@@ -188,6 +300,80 @@ fn build_conversation(
     items
 }
 
+/// Parts whose message is missing from storage, keyed by the top-level session they
+/// belong to (sub-agent parts go to the sub-agent's root session), in message-ID order.
+/// Parts whose session is missing too cannot be placed and are left out.
+pub fn orphaned_parts(data: &StorageData) -> HashMap<String, Vec<Part>> {
+    let message_ids: HashSet<&str> = data
+        .messages_by_session
+        .values()
+        .flatten()
+        .map(|m| m.id.as_str())
+        .collect();
+    let mut orphan_ids: Vec<&String> = data
+        .parts_by_message
+        .keys()
+        .filter(|id| !message_ids.contains(id.as_str()))
+        .collect();
+    orphan_ids.sort();
+
+    let mut by_session: HashMap<String, Vec<Part>> = HashMap::new();
+    for message_id in orphan_ids {
+        for part in &data.parts_by_message[message_id] {
+            if let Some(root) = root_session(data, &part.session_id) {
+                by_session
+                    .entry(root.to_string())
+                    .or_default()
+                    .push(part.clone());
+            }
+        }
+    }
+    by_session
+}
+
+/// Follow `parent_id` links up to the top-level session.
+pub fn root_session<'a>(data: &'a StorageData, session_id: &str) -> Option<&'a str> {
+    let mut session = data.sessions.get(session_id)?;
+    // Bounded so a parent cycle in corrupt storage can't loop forever
+    for _ in 0..data.sessions.len() {
+        match session
+            .parent_id
+            .as_deref()
+            .and_then(|p| data.sessions.get(p))
+        {
+            Some(parent) => session = parent,
+            None => break,
+        }
+    }
+    Some(&session.id)
+}
+
+/// Reverse a conversation to newest-first for `--reverse-messages`.
+///
+/// Each message is grouped with the sub-agents inlined directly after it (those it
+/// spawned), and the groups are reversed, so a sub-agent still follows its message.
+/// Sub-agents that precede the first message form their own group. Sub-agent
+/// conversations are reversed the same way.
+pub fn reverse_messages(items: Vec<ResolvedConversationItem>) -> Vec<ResolvedConversationItem> {
+    let mut groups: Vec<Vec<ResolvedConversationItem>> = Vec::new();
+    for item in items {
+        match item {
+            ResolvedConversationItem::Message(_) => groups.push(vec![item]),
+            ResolvedConversationItem::SubAgent { session, messages } => {
+                let sub = ResolvedConversationItem::SubAgent {
+                    session,
+                    messages: reverse_messages(messages),
+                };
+                match groups.last_mut() {
+                    Some(group) => group.push(sub),
+                    None => groups.push(vec![sub]),
+                }
+            }
+        }
+    }
+    groups.into_iter().rev().flatten().collect()
+}
+
 fn sum_tokens(messages: &[Message]) -> Tokens {
     let mut total = Tokens {
         input: Some(0),
@@ -209,3 +395,61 @@ fn sum_tokens(messages: &[Message]) -> Tokens {
     }
     total
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(id: &str) -> ResolvedConversationItem {
+        let message = serde_json::from_value(serde_json::json!({
+            "id": id, "sessionID": "ses_a", "role": "user"
+        }))
+        .unwrap();
+        ResolvedConversationItem::Message(ResolvedMessage {
+            message,
+            parts: Vec::new(),
+        })
+    }
+
+    fn sub_agent(id: &str, messages: Vec<ResolvedConversationItem>) -> ResolvedConversationItem {
+        let session = serde_json::from_value(serde_json::json!({
+            "id": id, "projectID": "prj", "parentID": "ses_a"
+        }))
+        .unwrap();
+        ResolvedConversationItem::SubAgent { session, messages }
+    }
+
+    fn ids(items: &[ResolvedConversationItem]) -> Vec<&str> {
+        items
+            .iter()
+            .map(|item| match item {
+                ResolvedConversationItem::Message(rm) => rm.message.id.as_str(),
+                ResolvedConversationItem::SubAgent { session, .. } => session.id.as_str(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn reverse_keeps_sub_agents_after_their_message() {
+        let items = vec![
+            message("m1"),
+            sub_agent("s1", vec![message("s1m1"), message("s1m2")]),
+            sub_agent("s2", Vec::new()),
+            message("m2"),
+        ];
+        let reversed = reverse_messages(items);
+        assert_eq!(ids(&reversed), ["m2", "m1", "s1", "s2"]);
+        match &reversed[2] {
+            ResolvedConversationItem::SubAgent { messages, .. } => {
+                assert_eq!(ids(messages), ["s1m2", "s1m1"])
+            }
+            ResolvedConversationItem::Message(_) => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn reverse_keeps_leading_sub_agents_together() {
+        let items = vec![sub_agent("s1", Vec::new()), message("m1"), message("m2")];
+        assert_eq!(ids(&reverse_messages(items)), ["m2", "m1", "s1"]);
+    }
+}
@@ -0,0 +1,314 @@
+use anyhow::{Context, Result};
+use rusqlite::{params, Connection, Transaction};
+use std::collections::HashSet;
+use std::path::Path;
+
+use crate::types::*;
+
+/// Row counts from one `export` call.
+#[derive(Debug, Default)]
+pub struct ExportCounts {
+    pub sessions: usize,
+    pub messages: usize,
+    pub parts: usize,
+}
+
+/// Write resolved projects into normalized tables in the SQLite file at `path`.
+///
+/// Sub-agent sessions get their own `sessions` row with `parent_id` set. A sub-agent
+/// exported on its own (`--session <sub-agent id>`) has no parent row to point at, so
+/// its `parent_id` is NULL. Timestamps are stored as epoch milliseconds.
+pub fn export(path: &Path, projects: &[ResolvedProject]) -> Result<ExportCounts> {
+    let mut conn = Connection::open(path).with_context(|| format!("opening {}", path.display()))?;
+    conn.execute_batch("PRAGMA foreign_keys = ON;")?;
+    let tx = conn.transaction()?;
+    // Existing copies are dropped first, so re-exporting into the same file replaces them
+    // while leaving any other tables alone
+    tx.execute_batch(
+        "
+        DROP TABLE IF EXISTS todos;
+        DROP TABLE IF EXISTS diffs;
+        DROP TABLE IF EXISTS parts;
+        DROP TABLE IF EXISTS messages;
+        DROP TABLE IF EXISTS sessions;
+        DROP TABLE IF EXISTS projects;
+
+        CREATE TABLE projects (
+            id       TEXT PRIMARY KEY,
+            worktree TEXT NOT NULL,
+            name     TEXT NOT NULL
+        );
+        CREATE TABLE sessions (
+            id         TEXT PRIMARY KEY,
+            project_id TEXT NOT NULL REFERENCES projects(id),
+            parent_id  TEXT REFERENCES sessions(id),
+            title      TEXT,
+            slug       TEXT,
+            version    TEXT,
+            created    INTEGER,
+            updated    INTEGER
+        );
+        CREATE TABLE messages (
+            id                 TEXT PRIMARY KEY,
+            session_id         TEXT NOT NULL REFERENCES sessions(id),
+            role               TEXT NOT NULL,
+            model              TEXT,
+            provider           TEXT,
+            mode               TEXT,
+            created            INTEGER,
+            completed          INTEGER,
+            cost               REAL,
+            tokens_input       INTEGER,
+            tokens_output      INTEGER,
+            tokens_reasoning   INTEGER,
+            tokens_cache_read  INTEGER,
+            tokens_cache_write INTEGER
+        );
+        CREATE TABLE parts (
+            id         TEXT PRIMARY KEY,
+            message_id TEXT NOT NULL REFERENCES messages(id),
+            seq        INTEGER NOT NULL,
+            type       TEXT NOT NULL,
+            text       TEXT,
+            tool       TEXT,
+            status     TEXT,
+            input      TEXT,
+            output     TEXT,
+            error      TEXT
+        );
+        CREATE TABLE diffs (
+            session_id TEXT NOT NULL REFERENCES sessions(id),
+            file       TEXT NOT NULL,
+            status     TEXT,
+            additions  INTEGER,
+            deletions  INTEGER,
+            before     TEXT,
+            after      TEXT
+        );
+        CREATE TABLE todos (
+            session_id TEXT NOT NULL REFERENCES sessions(id),
+            seq        INTEGER NOT NULL,
+            content    TEXT NOT NULL,
+            status     TEXT NOT NULL,
+            priority   TEXT
+        );
+        CREATE INDEX messages_session ON messages(session_id);
+        CREATE INDEX parts_message ON parts(message_id);
+        ",
+    )
+    .context("creating tables")?;
+
+    let mut counts = ExportCounts::default();
+    let exported: HashSet<&str> = projects
+        .iter()
+        .flat_map(|rp| &rp.sessions)
+        .map(|rs| rs.session.id.as_str())
+        .collect();
+    for rp in projects {
+        tx.execute(
+            "INSERT INTO projects (id, worktree, name) VALUES (?1, ?2, ?3)",
+            params![
+                rp.project.id,
+                rp.project.worktree,
+                rp.project.display_name()
+            ],
+        )?;
+        for rs in &rp.sessions {
+            // Sessions come oldest first, so an exported parent's row is already there
+            let parent = rs
+                .session
+                .parent_id
+                .as_deref()
+                .filter(|id| exported.contains(id));
+            insert_session(&tx, &rs.session, parent, &mut counts)?;
+            insert_items(&tx, &rs.messages, &mut counts)?;
+            for d in &rs.diffs {
+                tx.execute(
+                    "INSERT INTO diffs (session_id, file, status, additions, deletions, before, after)
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                    params![
+                        rs.session.id,
+                        d.file,
+                        d.status,
+                        d.additions,
+                        d.deletions,
+                        d.before,
+                        d.after
+                    ],
+                )?;
+            }
+            for (seq, t) in rs.todos.iter().enumerate() {
+                tx.execute(
+                    "INSERT INTO todos (session_id, seq, content, status, priority)
+                     VALUES (?1, ?2, ?3, ?4, ?5)",
+                    params![rs.session.id, seq, t.content, t.status, t.priority],
+                )?;
+            }
+        }
+    }
+
+    tx.commit().context("committing export")?;
+    Ok(counts)
+}
+
+fn insert_session(
+    tx: &Transaction,
+    s: &Session,
+    parent_id: Option<&str>,
+    counts: &mut ExportCounts,
+) -> Result<()> {
+    tx.execute(
+        "INSERT INTO sessions (id, project_id, parent_id, title, slug, version, created, updated)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+        params![
+            s.id,
+            s.project_id,
+            parent_id,
+            s.title,
+            s.slug,
+            s.version,
+            s.time.created.map(to_millis),
+            s.time.updated.map(to_millis)
+        ],
+    )
+    .with_context(|| format!("inserting session {}", s.id))?;
+    counts.sessions += 1;
+    Ok(())
+}
+
+fn insert_items(
+    tx: &Transaction,
+    items: &[ResolvedConversationItem],
+    counts: &mut ExportCounts,
+) -> Result<()> {
+    for item in items {
+        match item {
+            ResolvedConversationItem::Message(rm) => {
+                let m = &rm.message;
+                let tokens = m.tokens.clone().unwrap_or_default();
+                tx.execute(
+                    "INSERT INTO messages (id, session_id, role, model, provider, mode, created,
+                         completed, cost, tokens_input, tokens_output, tokens_reasoning,
+                         tokens_cache_read, tokens_cache_write)
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14)",
+                    params![
+                        m.id,
+                        m.session_id,
+                        m.role,
+                        m.effective_model(),
+                        m.effective_provider(),
+                        m.mode,
+                        m.time.created.map(to_millis),
+                        m.time.completed.map(to_millis),
+                        m.cost,
+                        tokens.input,
+                        tokens.output,
+                        tokens.reasoning,
+                        tokens.cache.read,
+                        tokens.cache.write
+                    ],
+                )
+                .with_context(|| format!("inserting message {}", m.id))?;
+                counts.messages += 1;
+
+                for (seq, part) in rm.parts.iter().enumerate() {
+                    insert_part(tx, part, seq)?;
+                    counts.parts += 1;
+                }
+            }
+            ResolvedConversationItem::SubAgent { session, messages } => {
+                insert_session(tx, session, session.parent_id.as_deref(), counts)?;
+                insert_items(tx, messages, counts)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Columns of a `parts` row beyond its IDs and type; unused ones stay NULL.
+#[derive(Default)]
+struct PartRow {
+    text: Option<String>,
+    tool: Option<String>,
+    status: Option<String>,
+    input: Option<String>,
+    output: Option<String>,
+    error: Option<String>,
+}
+
+fn insert_part(tx: &Transaction, part: &Part, seq: usize) -> Result<()> {
+    let (kind, row) = match &part.kind {
+        PartKind::Text { text, .. } => (
+            "text",
+            PartRow {
+                text: Some(text.clone()),
+                ..Default::default()
+            },
+        ),
+        PartKind::Tool { tool, state, .. } => (
+            "tool",
+            PartRow {
+                text: state.title.clone(),
+                tool: Some(tool.clone()),
+                status: state.status.clone(),
+                input: state.input.as_ref().map(|v| v.to_string()),
+                output: state.output.clone(),
+                error: state.error.clone(),
+            },
+        ),
+        PartKind::StepStart { .. } => ("step-start", PartRow::default()),
+        PartKind::StepFinish { reason, .. } => (
+            "step-finish",
+            PartRow {
+                status: reason.clone(),
+                ..Default::default()
+            },
+        ),
+        PartKind::Reasoning { text, .. } => (
+            "reasoning",
+            PartRow {
+                text: text.clone(),
+                ..Default::default()
+            },
+        ),
+        PartKind::Patch { files, .. } => (
+            "patch",
+            PartRow {
+                text: files.as_ref().map(|f| f.join("\n")),
+                ..Default::default()
+            },
+        ),
+        PartKind::Permission {
+            tool,
+            status,
+            reason,
+        } => (
+            "permission",
+            PartRow {
+                text: reason.clone(),
+                tool: tool.clone(),
+                status: status.clone(),
+                ..Default::default()
+            },
+        ),
+        PartKind::Unknown => ("unknown", PartRow::default()),
+    };
+    tx.execute(
+        "INSERT INTO parts (id, message_id, seq, type, text, tool, status, input, output, error)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+        params![
+            part.id,
+            part.message_id,
+            seq,
+            kind,
+            row.text,
+            row.tool,
+            row.status,
+            row.input,
+            row.output,
+            row.error
+        ],
+    )
+    .with_context(|| format!("inserting part {}", part.id))?;
+    Ok(())
+}
@@ -0,0 +1,83 @@
+use anyhow::{Context, Result};
+use serde_json::{json, Value};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use crate::types::*;
+
+/// Lightweight project → session hierarchy for `--tree`, without any message content.
+///
+/// ```json
+/// {
+///   "version": 1,
+///   "projects": [{
+///     "id": "…", "name": "…", "worktree": "…",
+///     "sessions": [{
+///       "id": "ses_…", "title": "…" | null, "slug": "…" | null,
+///       "created": 1736000000000 | null, "updated": … | null,
+///       "messages": 12, "sub_agents": 2
+///     }]
+///   }]
+/// }
+/// ```
+///
+/// Timestamps are epoch milliseconds. `messages` counts the session's own messages;
+/// `sub_agents` counts sub-agent sessions at any depth.
+pub fn tree(projects: &[ResolvedProject]) -> Value {
+    let projects: Vec<Value> = projects
+        .iter()
+        .map(|rp| {
+            let sessions: Vec<Value> = rp
+                .sessions
+                .iter()
+                .map(|rs| {
+                    let s = &rs.session;
+                    let messages = rs
+                        .messages
+                        .iter()
+                        .filter(|item| matches!(item, ResolvedConversationItem::Message(_)))
+                        .count();
+                    json!({
+                        "id": s.id,
+                        "title": s.title,
+                        "slug": s.slug,
+                        "created": s.time.created.map(to_millis),
+                        "updated": s.time.updated.map(to_millis),
+                        "messages": messages,
+                        "sub_agents": count_sub_agents(&rs.messages),
+                    })
+                })
+                .collect();
+            json!({
+                "id": rp.project.id,
+                "name": rp.project.display_name(),
+                "worktree": rp.project.worktree,
+                "sessions": sessions,
+            })
+        })
+        .collect();
+    // Schema version of the document; bumped on incompatible changes
+    json!({ "version": 1, "projects": projects })
+}
+
+fn count_sub_agents(items: &[ResolvedConversationItem]) -> usize {
+    items
+        .iter()
+        .map(|item| match item {
+            ResolvedConversationItem::SubAgent { messages, .. } => 1 + count_sub_agents(messages),
+            ResolvedConversationItem::Message(_) => 0,
+        })
+        .sum()
+}
+
+/// Write the `--tree` document to `path` as pretty-printed JSON.
+pub fn write(path: &Path, projects: &[ResolvedProject]) -> Result<()> {
+    let file = File::create(path).with_context(|| format!("creating {}", path.display()))?;
+    let mut out = BufWriter::new(file);
+    serde_json::to_writer_pretty(&mut out, &tree(projects))
+        .with_context(|| format!("writing {}", path.display()))?;
+    writeln!(out)
+        .and_then(|_| out.flush())
+        .with_context(|| format!("writing {}", path.display()))
+}
@@ -1,7 +1,32 @@
+use chrono::{DateTime, TimeZone, Utc};
+use serde::de::IgnoredAny;
 use serde::Deserialize;
+use std::ops::Range;
 
 // ── Timestamps ──────────────────────────────────────────────────────
 
+/// Normalise a stored timestamp to epoch milliseconds, accepting seconds too.
+pub fn to_millis(ts: u64) -> u64 {
+    // Below 1e11 it is seconds: 1e11 ms is March 1973, while 1e11 s is far beyond
+    // year 5000, so real OpenCode data can't be ambiguous on either side
+    if ts < 100_000_000_000 {
+        ts.saturating_mul(1000)
+    } else {
+        ts
+    }
+}
+
+/// Convert a stored timestamp (ms, or seconds if implausibly small) to a UTC datetime.
+pub fn to_datetime(ts: u64) -> Option<DateTime<Utc>> {
+    let ms = to_millis(ts);
+    let secs = (ms / 1000) as i64;
+    let nanos = ((ms % 1000) * 1_000_000) as u32;
+    match Utc.timestamp_opt(secs, nanos) {
+        chrono::LocalResult::Single(dt) => Some(dt),
+        _ => None,
+    }
+}
+
 #[allow(dead_code)]
 #[derive(Debug, Deserialize, Clone, Default)]
 pub struct ProjectTime {
@@ -32,6 +57,19 @@ pub struct PartTime {
     pub compacted: Option<u64>,
 }
 
+// ── Text ────────────────────────────────────────────────────────────
+
+/// Collapse whitespace onto one line and cut to `max` characters.
+pub fn one_line(text: &str, max: usize) -> String {
+    let flat = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    if flat.chars().count() > max {
+        let cut: String = flat.chars().take(max).collect();
+        format!("{}...", cut.trim_end())
+    } else {
+        flat
+    }
+}
+
 // ── Project ─────────────────────────────────────────────────────────
 
 #[allow(dead_code)]
@@ -87,30 +125,68 @@ pub struct Session {
 }
 
 impl Session {
-    /// Filename-safe slug for the session, using the slug field or title.
-    pub fn file_stem(&self, date_str: &str) -> String {
+    /// Filename-safe slug for the session, using the slug field, title, `fallback`, or ID;
+    /// keeps letters, digits, `-` and `_`.
+    pub fn file_stem(&self, date_str: &str, fallback: Option<&str>) -> String {
+        self.file_stem_allowing(date_str, fallback, "-_")
+    }
+
+    /// `file_stem` keeping letters, digits, and the characters in `allow`; anything else
+    /// becomes `-`.
+    pub fn file_stem_allowing(
+        &self,
+        date_str: &str,
+        fallback: Option<&str>,
+        allow: &str,
+    ) -> String {
         let name = self
             .slug
             .as_deref()
             .or(self.title.as_deref())
+            .or(fallback)
             .unwrap_or(&self.id);
         let sanitized: String = name
             .chars()
             .map(|c| {
-                if c.is_alphanumeric() || c == '-' || c == '_' {
+                if c.is_alphanumeric() || allow.contains(c) {
                     c
                 } else {
                     '-'
                 }
             })
             .collect();
-        let truncated = if sanitized.len() > 60 {
-            &sanitized[..60]
-        } else {
-            &sanitized
-        };
+        // Cut on a char boundary: titles may contain non-ASCII letters
+        let truncated: String = sanitized.chars().take(60).collect();
         format!("{}_{}", date_str, truncated.trim_end_matches('-'))
     }
+
+    /// opencode versions whose storage these types parse, as `from..before`: every 0.x and
+    /// 1.x release, which share the `storage/` JSON layout. A new major version is where
+    /// that layout may change incompatibly.
+    pub fn supported_versions() -> Range<(u64, u64, u64)> {
+        (0, 0, 0)..(2, 0, 0)
+    }
+
+    /// `supported_versions()` for messages, e.g. `before 2.0.0`.
+    pub fn supported_versions_label() -> String {
+        let (major, minor, patch) = Self::supported_versions().end;
+        format!("before {}.{}.{}", major, minor, patch)
+    }
+
+    /// Whether the opencode version that wrote the session is in `supported_versions()`;
+    /// `None` when it is missing or not a `major.minor.patch` number. Other versions still
+    /// load, but may hold part types or fields that come out as `Unknown` or go missing.
+    pub fn version_supported(&self) -> Option<bool> {
+        let version = self.version.as_deref()?.trim_start_matches('v');
+        // Pre-release and build suffixes (`1.2.0-beta.1`) don't move it across a bound
+        let core = version.split(['-', '+']).next()?;
+        let mut nums = core.split('.').map(|n| n.parse::<u64>().ok());
+        let parsed = (nums.next()??, nums.next()??, nums.next()??);
+        if nums.next().is_some() {
+            return None;
+        }
+        Some(Self::supported_versions().contains(&parsed))
+    }
 }
 
 // ── Message ─────────────────────────────────────────────────────────
@@ -139,6 +215,27 @@ pub struct Tokens {
     pub cache: TokenCache,
 }
 
+impl Tokens {
+    /// Input + output + reasoning; cache reads/writes are not counted.
+    pub fn total(&self) -> u64 {
+        self.input.unwrap_or(0) + self.output.unwrap_or(0) + self.reasoning.unwrap_or(0)
+    }
+
+    /// Add `other`'s counts to these; a count missing on both sides stays `None`.
+    pub fn add(&mut self, other: &Tokens) {
+        fn sum(a: &mut Option<u64>, b: Option<u64>) {
+            if let Some(b) = b {
+                *a = Some(a.unwrap_or(0) + b);
+            }
+        }
+        sum(&mut self.input, other.input);
+        sum(&mut self.output, other.output);
+        sum(&mut self.reasoning, other.reasoning);
+        sum(&mut self.cache.read, other.cache.read);
+        sum(&mut self.cache.write, other.cache.write);
+    }
+}
+
 #[allow(dead_code)]
 #[derive(Debug, Deserialize, Clone, Default)]
 pub struct MessagePath {
@@ -172,6 +269,9 @@ pub struct Message {
     pub cost: Option<f64>,
     pub tokens: Option<Tokens>,
     pub finish: Option<String>,
+    /// Set when the request failed (`{"name": "APIError", "data": {...}}`); only its
+    /// presence is kept
+    pub error: Option<IgnoredAny>,
 }
 
 impl Message {
@@ -181,6 +281,13 @@ impl Message {
             .as_deref()
             .or(self.model.as_ref().and_then(|m| m.model_id.as_deref()))
     }
+
+    /// Get the provider ID regardless of whether this is a user or assistant message.
+    pub fn effective_provider(&self) -> Option<&str> {
+        self.provider_id
+            .as_deref()
+            .or(self.model.as_ref().and_then(|m| m.provider_id.as_deref()))
+    }
 }
 
 // ── Part ────────────────────────────────────────────────────────────
@@ -235,6 +342,14 @@ pub enum PartKind {
         hash: Option<String>,
         files: Option<Vec<String>>,
     },
+    /// A tool-use permission request and the user's answer.
+    #[serde(rename = "permission")]
+    Permission {
+        tool: Option<String>,
+        /// `granted`, `denied`, or absent while still pending
+        status: Option<String>,
+        reason: Option<String>,
+    },
     #[serde(other)]
     Unknown,
 }
@@ -293,6 +408,90 @@ pub struct ResolvedSession {
     pub diffs: Vec<DiffEntry>,
     pub todos: Vec<TodoEntry>,
     pub token_totals: Tokens,
+    pub cost_total: f64,
+    /// Parts whose message is missing from storage; only filled for `--render-orphans`
+    pub orphan_parts: Vec<Part>,
+    /// Conversation items dropped from the end of `messages` by `--head` or
+    /// `--message-until`
+    pub omitted_items: usize,
+    /// Conversation items dropped from the start of `messages` by `--message-since`
+    pub omitted_before: usize,
+    /// For a sub-agent session resolved on its own: its parent, grandparent, ... up to
+    /// the top-level session. Empty for top-level sessions.
+    pub ancestors: Vec<Session>,
+    /// The first user text flattened to one line (~60 chars), taken at resolve time so
+    /// that reordering or trimming `messages` doesn't change it
+    pub first_prompt: Option<String>,
+}
+
+impl ResolvedSession {
+    /// Whether any tool call in the session, or its sub-agents, ended in error.
+    pub fn has_tool_errors(&self) -> bool {
+        fn any_error(items: &[ResolvedConversationItem]) -> bool {
+            items.iter().any(|item| match item {
+                ResolvedConversationItem::Message(rm) => rm.parts.iter().any(|p| {
+                    matches!(&p.kind, PartKind::Tool { state, .. }
+                        if state.status.as_deref() == Some("error") || state.error.is_some())
+                }),
+                ResolvedConversationItem::SubAgent { messages, .. } => any_error(messages),
+            })
+        }
+        any_error(&self.messages)
+    }
+
+    /// Whether the session changed files: it has diffs, or its summary counts changed files.
+    pub fn has_file_changes(&self) -> bool {
+        !self.diffs.is_empty() || self.session.summary.files.unwrap_or(0) > 0
+    }
+
+    /// `token_totals` and `cost_total` plus the tokens and cost of every sub-agent
+    /// message, at any depth.
+    pub fn totals_with_sub_agents(&self) -> (Tokens, f64) {
+        fn add(items: &[ResolvedConversationItem], in_sub: bool, t: &mut Tokens, cost: &mut f64) {
+            for item in items {
+                match item {
+                    ResolvedConversationItem::Message(rm) if in_sub => {
+                        if let Some(ref m) = rm.message.tokens {
+                            t.add(m);
+                        }
+                        *cost += rm.message.cost.unwrap_or(0.0);
+                    }
+                    ResolvedConversationItem::Message(_) => {}
+                    ResolvedConversationItem::SubAgent { messages, .. } => {
+                        add(messages, true, t, cost)
+                    }
+                }
+            }
+        }
+        let (mut tokens, mut cost) = (self.token_totals.clone(), self.cost_total);
+        add(&self.messages, false, &mut tokens, &mut cost);
+        (tokens, cost)
+    }
+
+    /// Whether the session's last assistant message has no `finish` reason, no error,
+    /// and no completion time yet, which means opencode may still be writing it.
+    pub fn in_progress(&self) -> bool {
+        // Latest by time rather than position, so `--reverse-messages` doesn't matter
+        self.messages
+            .iter()
+            .filter_map(|item| match item {
+                ResolvedConversationItem::Message(rm) if rm.message.role == "assistant" => {
+                    Some(&rm.message)
+                }
+                _ => None,
+            })
+            .max_by_key(|m| m.time.created.unwrap_or(0))
+            .is_some_and(|m| m.finish.is_none() && m.error.is_none() && m.time.completed.is_none())
+    }
+
+    /// A title taken from the first user text (~60 chars), for sessions with neither
+    /// a title nor a slug.
+    pub fn derived_title(&self) -> Option<String> {
+        if self.session.title.is_some() || self.session.slug.is_some() {
+            return None;
+        }
+        self.first_prompt.clone()
+    }
 }
 
 /// An item in the conversation flow — either a normal message or an inlined sub-agent.
@@ -311,3 +510,81 @@ pub struct ResolvedProject {
     pub project: Project,
     pub sessions: Vec<ResolvedSession>,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn millisecond_timestamps_are_kept() {
+        assert_eq!(to_millis(1_736_000_000_123), 1_736_000_000_123);
+    }
+
+    #[test]
+    fn second_timestamps_are_scaled() {
+        assert_eq!(to_millis(1_736_000_000), 1_736_000_000_000);
+        assert_eq!(to_datetime(1_736_000_000), to_datetime(1_736_000_000_000));
+        assert_eq!(
+            to_datetime(1_736_000_000).unwrap().to_rfc3339(),
+            "2025-01-04T14:13:20+00:00"
+        );
+    }
+
+    #[test]
+    fn versions_before_two_are_supported() {
+        let session = |version: &str| Session {
+            version: Some(version.to_string()),
+            ..serde_json::from_str(r#"{"id": "ses_a", "projectID": "prj"}"#).unwrap()
+        };
+        assert_eq!(session("0.3.1").version_supported(), Some(true));
+        assert_eq!(session("1.0.5").version_supported(), Some(true));
+        assert_eq!(session("v1.1.53").version_supported(), Some(true));
+        assert_eq!(session("2.0.0-beta.1").version_supported(), Some(false));
+        assert_eq!(session("dev").version_supported(), None);
+    }
+
+    /// A session holding one assistant message built from `fields`.
+    fn with_assistant(fields: serde_json::Value) -> ResolvedSession {
+        let mut message = serde_json::json!({
+            "id": "msg_a", "sessionID": "ses_a", "role": "assistant", "time": {"created": 1}
+        });
+        if let (Some(map), serde_json::Value::Object(extra)) = (message.as_object_mut(), fields) {
+            map.extend(extra);
+        }
+        ResolvedSession {
+            session: serde_json::from_str(r#"{"id": "ses_a", "projectID": "prj"}"#).unwrap(),
+            messages: vec![ResolvedConversationItem::Message(ResolvedMessage {
+                message: serde_json::from_value(message).unwrap(),
+                parts: Vec::new(),
+            })],
+            diffs: Vec::new(),
+            todos: Vec::new(),
+            token_totals: Tokens::default(),
+            cost_total: 0.0,
+            orphan_parts: Vec::new(),
+            omitted_items: 0,
+            omitted_before: 0,
+            ancestors: Vec::new(),
+            first_prompt: None,
+        }
+    }
+
+    #[test]
+    fn unfinished_assistant_message_is_in_progress() {
+        assert!(with_assistant(serde_json::json!({})).in_progress());
+        assert!(!with_assistant(serde_json::json!({"finish": "stop"})).in_progress());
+    }
+
+    #[test]
+    fn failed_assistant_message_is_not_in_progress() {
+        let error =
+            serde_json::json!({"error": {"name": "APIError", "data": {"message": "overloaded"}}});
+        assert!(!with_assistant(error).in_progress());
+    }
+
+    #[test]
+    fn completed_assistant_message_is_not_in_progress() {
+        let completed = serde_json::json!({"time": {"created": 1, "completed": 2}});
+        assert!(!with_assistant(completed).in_progress());
+    }
+}
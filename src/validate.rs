@@ -0,0 +1,123 @@
+use std::collections::HashSet;
+
+use crate::loader::{self, StorageData};
+use crate::types::*;
+
+/// One cross-reference problem found by `--validate`.
+#[derive(Debug)]
+pub struct Problem {
+    /// Short name of the failed check, e.g. `missing-parent`
+    pub check: &'static str,
+    pub detail: String,
+}
+
+/// Check that the loaded entities reference each other consistently.
+///
+/// Problems come back sorted by check, then detail, so reports are stable across runs.
+pub fn validate(data: &StorageData) -> Vec<Problem> {
+    let mut problems = Vec::new();
+    let mut report = |check, detail| problems.push(Problem { check, detail });
+
+    let project_ids: HashSet<&str> = data.projects.iter().map(|p| p.id.as_str()).collect();
+
+    for session in data.sessions.values() {
+        if !project_ids.contains(session.project_id.as_str()) {
+            report(
+                "missing-project",
+                format!(
+                    "session {} references project {}",
+                    session.id, session.project_id
+                ),
+            );
+        }
+        if let Some(ref pid) = session.parent_id {
+            if !data.sessions.contains_key(pid) {
+                report(
+                    "missing-parent",
+                    format!("session {} references parent {}", session.id, pid),
+                );
+            }
+        }
+        if data
+            .messages_by_session
+            .get(&session.id)
+            .is_none_or(|msgs| msgs.is_empty())
+        {
+            report(
+                "empty-session",
+                format!("session {} has no messages", session.id),
+            );
+        }
+    }
+
+    let mut message_ids: HashSet<&str> = HashSet::new();
+    for (session_id, messages) in &data.messages_by_session {
+        if !data.sessions.contains_key(session_id) {
+            report(
+                "missing-session",
+                format!(
+                    "{} messages stored under unknown session {}",
+                    messages.len(),
+                    session_id
+                ),
+            );
+        }
+        for m in messages {
+            message_ids.insert(m.id.as_str());
+            if m.session_id != *session_id {
+                report(
+                    "misfiled-message",
+                    format!(
+                        "message {} is stored under session {} but belongs to {}",
+                        m.id, session_id, m.session_id
+                    ),
+                );
+            }
+        }
+    }
+
+    for (message_id, parts) in &data.parts_by_message {
+        if !message_ids.contains(message_id.as_str()) {
+            report(
+                "missing-message",
+                format!(
+                    "{} parts stored under unknown message {}",
+                    parts.len(),
+                    message_id
+                ),
+            );
+        }
+    }
+
+    for session_id in data.diffs_by_session.keys() {
+        if !data.sessions.contains_key(session_id) {
+            report(
+                "orphaned-diff",
+                format!("session_diff for unknown session {}", session_id),
+            );
+        }
+    }
+    for session_id in data.todos_by_session.keys() {
+        if !data.sessions.contains_key(session_id) {
+            report(
+                "orphaned-todo",
+                format!("todo for unknown session {}", session_id),
+            );
+        }
+    }
+
+    for (version, count) in loader::unsupported_versions(data.sessions.values()) {
+        report(
+            "unsupported-version",
+            format!(
+                "{} sessions written by opencode {} (supported: {})",
+                count,
+                version,
+                Session::supported_versions_label()
+            ),
+        );
+    }
+
+    problems.sort_by(|a, b| (a.check, &a.detail).cmp(&(b.check, &b.detail)));
+    problems
+}
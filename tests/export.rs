@@ -0,0 +1,115 @@
+use std::path::Path;
+
+use oc_export::renderer::{self, RenderOptions};
+use oc_export::resolver;
+use oc_export::types::*;
+use oc_export::OcExport;
+
+/// `tests/fixtures/storage`: one project with one session, `ses_main`.
+fn fixture() -> OcExport {
+    let storage = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/storage");
+    OcExport::open(&storage).unwrap()
+}
+
+fn render_main(export: &OcExport) -> String {
+    let resolved = export.resolve("ses_main").unwrap();
+    let project = export.project("prj_fixture").unwrap();
+    renderer::render_session(&resolved, project, &RenderOptions::default())
+}
+
+#[test]
+fn permission_parts_render_as_notes() {
+    let md = render_main(&fixture());
+    assert!(md.contains("> 🔒 Permission granted for `bash`: run cargo test\n"));
+    assert!(md.contains("> 🔒 Permission denied for `edit`\n"));
+}
+
+#[test]
+fn fenced_content_keeps_its_wrapper() {
+    let md = render_main(&fixture());
+    // Tool output
+    assert!(md.contains(
+        "**Output:**\n````\nfailures:\n```\nassertion failed: left == right\n```\n\
+         test result: FAILED\n````\n"
+    ));
+    // `write` content
+    assert!(md.contains("````md\n# Notes\n\n```sh\ncargo test\n```\n````\n"));
+    // `bash` command
+    assert!(md.contains("````bash\ncat > run.md <<'EOF'\n```\ncargo test\n```\nEOF\n````\n"));
+}
+
+#[test]
+fn duplicate_message_file_renders_one_turn() {
+    let export = fixture();
+    // msg_002_conflict.json repeats msg_002 with an earlier completion time
+    let copies: Vec<&Message> = export
+        .messages("ses_main")
+        .iter()
+        .filter(|m| m.id == "msg_002")
+        .collect();
+    assert_eq!(copies.len(), 1);
+    assert_eq!(copies[0].time.completed, Some(1736000000900));
+
+    let md = render_main(&export);
+    assert_eq!(md.matches("\n## Assistant").count(), 2);
+    // prt_003_copy.json repeats the `cargo test` part
+    assert_eq!(md.matches("> Run tests\n").count(), 1);
+}
+
+#[test]
+fn mcp_tool_heading_names_server_and_tool() {
+    let md = render_main(&fixture());
+    assert!(md.contains("### MCP Tool: github / create_issue\n"));
+    assert!(!md.contains("mcp__github__create_issue"));
+}
+
+/// Message IDs and sub-agent session IDs in conversation order.
+fn item_ids(items: &[ResolvedConversationItem]) -> Vec<&str> {
+    items
+        .iter()
+        .map(|item| match item {
+            ResolvedConversationItem::Message(rm) => rm.message.id.as_str(),
+            ResolvedConversationItem::SubAgent { session, .. } => session.id.as_str(),
+        })
+        .collect()
+}
+
+#[test]
+fn reversed_messages_keep_the_first_prompt_as_title() {
+    let export = fixture();
+    let mut resolved = export.resolve("ses_main").unwrap();
+    assert_eq!(
+        item_ids(&resolved.messages),
+        ["msg_001", "msg_002", "ses_sub", "msg_003", "msg_004"]
+    );
+
+    resolved.messages = resolver::reverse_messages(std::mem::take(&mut resolved.messages));
+    assert_eq!(
+        item_ids(&resolved.messages),
+        ["msg_004", "msg_003", "msg_002", "ses_sub", "msg_001"]
+    );
+    assert_eq!(
+        resolved.derived_title().as_deref(),
+        Some("Run the test suite and report what fails")
+    );
+}
+
+#[test]
+fn toc_links_match_turn_heading_anchors() {
+    let export = fixture();
+    let resolved = export.resolve("ses_main").unwrap();
+    let project = export.project("prj_fixture").unwrap();
+    let opts = RenderOptions {
+        toc: true,
+        ..Default::default()
+    };
+    let md = renderer::render_session(&resolved, project, &opts);
+    assert!(md.contains("1. [User](#user-1): Run the test suite and report what fails\n"));
+    assert!(md.contains("2. [Assistant (claude-sonnet-4)](#assistant-2)\n"));
+    assert!(md.contains("\n## User 1\n"));
+    assert!(md.contains("\n## Assistant 2\n\n*claude-sonnet-4*\n"));
+    assert!(md.contains("\n## Assistant 4\n"));
+    // Nested headings stay clear of the `user-<n>` anchors
+    assert!(md.contains("> ## User (sub-agent)\n"));
+    assert!(!md.contains("<a id="));
+}